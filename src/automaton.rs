@@ -0,0 +1,506 @@
+//! Generic 2D cellular automaton engine shared by grid simulating days.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Signed (row, col) position or offset, may point outside of the grid.
+pub type Coord = (isize, isize);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Neighborhood {
+    /// all 8 surrounding cells
+    Moore,
+    /// 4 orthogonally adjacent cells
+    VonNeumann,
+    /// arbitrary (row, col) offsets, may include the cell itself
+    Custom(Vec<Coord>),
+}
+
+impl Neighborhood {
+    /// Offsets in row-major order, that is the order of neighbors passed to `LocalRule::next`.
+    pub fn offsets(&self) -> Vec<Coord> {
+        match self {
+            Neighborhood::Moore => vec![
+                (-1, -1),
+                (-1, 0),
+                (-1, 1),
+                (0, -1),
+                (0, 1),
+                (1, -1),
+                (1, 0),
+                (1, 1),
+            ],
+            Neighborhood::VonNeumann => vec![(-1, 0), (0, -1), (0, 1), (1, 0)],
+            Neighborhood::Custom(offsets) => offsets.clone(),
+        }
+    }
+
+    /// Farthest reach of the neighborhood along either axis.
+    pub fn radius(&self) -> usize {
+        self.offsets()
+            .iter()
+            .map(|(r, c)| r.unsigned_abs().max(c.unsigned_abs()))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Edge {
+    /// cells beyond the grid do not exist, reading as constant background
+    Bounded,
+    /// grid wraps around in both directions
+    Toroidal,
+    /// grid is surrounded by infinite plane of background cells, evolving by the rule as well,
+    /// so the grid grows by neighborhood radius on each side in every step
+    Infinite,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Grid<T> {
+    rows: usize,
+    cols: usize,
+    cells: Vec<T>,
+    edge: Edge,
+    background: T,
+}
+
+impl<T: Clone> Grid<T> {
+    pub fn new(rows: usize, cols: usize, edge: Edge, background: T) -> Self {
+        Self {
+            rows,
+            cols,
+            cells: vec![background.clone(); rows * cols],
+            edge,
+            background,
+        }
+    }
+
+    /// Grid made of equally long rows of cells.
+    pub fn from_rows(rows: Vec<Vec<T>>, edge: Edge, background: T) -> Self {
+        let cols = rows.first().map(|row| row.len()).unwrap_or(0);
+        assert!(rows.iter().all(|row| row.len() == cols));
+        Self {
+            rows: rows.len(),
+            cols,
+            cells: rows.into_iter().flatten().collect(),
+            edge,
+            background,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn edge(&self) -> Edge {
+        self.edge
+    }
+
+    pub fn background(&self) -> &T {
+        &self.background
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> &T {
+        &self.cells[row * self.cols + col]
+    }
+
+    pub fn get_mut(&mut self, row: usize, col: usize) -> &mut T {
+        &mut self.cells[row * self.cols + col]
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, cell: T) {
+        self.cells[row * self.cols + col] = cell;
+    }
+
+    /// Position of existing grid cell for any coordinate, respecting the edge type.
+    pub fn resolve(&self, (row, col): Coord) -> Option<(usize, usize)> {
+        if self.rows == 0 || self.cols == 0 {
+            return None;
+        }
+        match self.edge {
+            Edge::Toroidal => Some((
+                row.rem_euclid(self.rows as isize) as usize,
+                col.rem_euclid(self.cols as isize) as usize,
+            )),
+            Edge::Bounded | Edge::Infinite => {
+                if row < 0 || col < 0 || row >= self.rows as isize || col >= self.cols as isize {
+                    None
+                } else {
+                    Some((row as usize, col as usize))
+                }
+            }
+        }
+    }
+
+    /// Cell at any coordinate, background when beyond the edge.
+    pub fn at(&self, coord: Coord) -> &T {
+        match self.resolve(coord) {
+            Some((row, col)) => self.get(row, col),
+            None => &self.background,
+        }
+    }
+
+    /// Neighbor cells in the neighborhood order, background filled in beyond the edge.
+    pub fn neighbors(&self, row: usize, col: usize, neighborhood: &Neighborhood) -> Vec<&T> {
+        neighborhood
+            .offsets()
+            .iter()
+            .map(|(dr, dc)| self.at((row as isize + dr, col as isize + dc)))
+            .collect()
+    }
+
+    /// Positions of neighbors which exist within the grid.
+    pub fn neighbor_coords(
+        &self,
+        row: usize,
+        col: usize,
+        neighborhood: &Neighborhood,
+    ) -> Vec<(usize, usize)> {
+        neighborhood
+            .offsets()
+            .iter()
+            .filter_map(|(dr, dc)| self.resolve((row as isize + dr, col as isize + dc)))
+            .collect()
+    }
+
+    pub fn coords(&self) -> impl Iterator<Item = (usize, usize)> {
+        let cols = self.cols;
+        (0..self.rows).flat_map(move |r| (0..cols).map(move |c| (r, c)))
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = &T> {
+        self.cells.iter()
+    }
+
+    pub fn cells_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.cells.iter_mut()
+    }
+
+    pub fn count(&self, predicate: impl Fn(&T) -> bool) -> usize {
+        self.cells.iter().filter(|cell| predicate(cell)).count()
+    }
+
+    /// Same grid surrounded by `n` layers of background cells.
+    pub fn padded(&self, n: usize) -> Self {
+        let mut grid = Self::new(
+            self.rows + 2 * n,
+            self.cols + 2 * n,
+            self.edge,
+            self.background.clone(),
+        );
+        for (row, col) in self.coords() {
+            grid.set(row + n, col + n, self.get(row, col).clone());
+        }
+        grid
+    }
+
+    pub fn render(&self, to_char: impl Fn(&T) -> char) -> String {
+        let mut s = String::with_capacity((self.cols + 1) * self.rows);
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                s.push(to_char(self.get(row, col)));
+            }
+            s.push('\n');
+        }
+        s
+    }
+}
+
+/// Transition of the whole grid by a single step.
+pub trait Rule {
+    type Cell: Clone + PartialEq;
+
+    fn step(&self, grid: &Grid<Self::Cell>) -> Grid<Self::Cell>;
+}
+
+/// Rule where new state of every cell depends only on the cell and its neighborhood,
+/// all cells being updated at once. Step may be split into several such phases.
+pub trait LocalRule {
+    type Cell: Clone + PartialEq;
+
+    fn neighborhood(&self) -> Neighborhood;
+
+    fn phases(&self) -> usize {
+        1
+    }
+
+    /// New state of a cell, neighbors come in `Neighborhood::offsets` order.
+    fn next(&self, phase: usize, cell: &Self::Cell, neighbors: &[&Self::Cell]) -> Self::Cell;
+}
+
+impl<R: LocalRule> Rule for R {
+    type Cell = R::Cell;
+
+    fn step(&self, grid: &Grid<Self::Cell>) -> Grid<Self::Cell> {
+        let neighborhood = self.neighborhood();
+        let mut grid = grid.clone();
+        for phase in 0..self.phases() {
+            grid = local_phase(self, phase, &neighborhood, &grid);
+        }
+        grid
+    }
+}
+
+fn local_phase<R: LocalRule>(
+    rule: &R,
+    phase: usize,
+    neighborhood: &Neighborhood,
+    grid: &Grid<R::Cell>,
+) -> Grid<R::Cell> {
+    let offsets = neighborhood.offsets();
+    let (grow, background) = match grid.edge {
+        Edge::Infinite => {
+            let bg = &grid.background;
+            let neighbors = vec![bg; offsets.len()];
+            (neighborhood.radius(), rule.next(phase, bg, &neighbors))
+        }
+        Edge::Bounded | Edge::Toroidal => (0, grid.background.clone()),
+    };
+
+    let rows = grid.rows + 2 * grow;
+    let cols = grid.cols + 2 * grow;
+    let mut cells = Vec::with_capacity(rows * cols);
+    for row in 0..rows {
+        for col in 0..cols {
+            let (r, c) = (row as isize - grow as isize, col as isize - grow as isize);
+            let cell = grid.at((r, c));
+            let neighbors: Vec<&R::Cell> = offsets
+                .iter()
+                .map(|(dr, dc)| grid.at((r + dr, c + dc)))
+                .collect();
+            cells.push(rule.next(phase, cell, &neighbors));
+        }
+    }
+
+    Grid {
+        rows,
+        cols,
+        cells,
+        edge: grid.edge,
+        background,
+    }
+}
+
+/// Repeating sequence of states - state after `start` steps is the same as after `start + length`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cycle {
+    pub start: usize,
+    pub length: usize,
+}
+
+/// How did the run of automaton end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// desired state got reached in given step
+    Reached(usize),
+    /// states started to repeat without ever reaching the desired one
+    Cycled(Cycle),
+    /// gave up after maximal number of steps, undecided
+    StepLimit,
+}
+
+#[derive(Debug, Clone)]
+pub struct Automaton<R: Rule> {
+    pub rule: R,
+    pub grid: Grid<R::Cell>,
+    /// number of steps performed so far
+    pub steps: usize,
+}
+
+impl<R: Rule> Automaton<R> {
+    pub fn new(rule: R, grid: Grid<R::Cell>) -> Self {
+        Self {
+            rule,
+            grid,
+            steps: 0,
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.grid = self.rule.step(&self.grid);
+        self.steps += 1;
+    }
+
+    /// Performs single step, returns whether anything changed.
+    /// Mere growth of infinite grid by unchanged background is not considered a change.
+    pub fn step_changed(&mut self) -> bool {
+        let next = self.rule.step(&self.grid);
+        let changed = if next.rows > self.grid.rows {
+            next != self.grid.padded((next.rows - self.grid.rows) / 2)
+        } else {
+            next != self.grid
+        };
+        self.grid = next;
+        self.steps += 1;
+        changed
+    }
+
+    pub fn run(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.step();
+        }
+    }
+
+    /// Steps until the grid satisfies the predicate, returns the number of the step that got there.
+    /// Gives up with `None` after `max_steps` steps, if given.
+    pub fn run_until(
+        &mut self,
+        max_steps: Option<usize>,
+        predicate: impl Fn(&Grid<R::Cell>) -> bool,
+    ) -> Option<usize> {
+        let limit = max_steps.map(|max| self.steps + max);
+        while limit.is_none_or(|limit| self.steps < limit) {
+            self.step();
+            if predicate(&self.grid) {
+                return Some(self.steps);
+            }
+        }
+        None
+    }
+
+    /// Steps until nothing changes, returns the number of the first step without any change.
+    pub fn run_until_stable(&mut self, max_steps: Option<usize>) -> Option<usize> {
+        let limit = max_steps.map(|max| self.steps + max);
+        while limit.is_none_or(|limit| self.steps < limit) {
+            if !self.step_changed() {
+                return Some(self.steps);
+            }
+        }
+        None
+    }
+}
+
+impl<R: Rule> Automaton<R>
+where
+    R::Cell: Hash + Eq,
+{
+    /// Steps until some already seen state repeats. Stable grid is a cycle of length 1.
+    pub fn run_until_cycle(&mut self, max_steps: Option<usize>) -> Option<Cycle> {
+        match self.run_until_or_cycle(max_steps, |_| false) {
+            Outcome::Cycled(cycle) => Some(cycle),
+            Outcome::Reached(_) | Outcome::StepLimit => None,
+        }
+    }
+
+    /// Steps until the grid satisfies the predicate, or until it gets into a cycle
+    /// without ever satisfying it.
+    pub fn run_until_or_cycle(
+        &mut self,
+        max_steps: Option<usize>,
+        predicate: impl Fn(&Grid<R::Cell>) -> bool,
+    ) -> Outcome {
+        self.run_until_or_cycle_by(max_steps, predicate, |grid| grid.clone())
+    }
+}
+
+impl<R: Rule> Automaton<R> {
+    /// Same as `run_until_or_cycle`, with states told apart by the key only,
+    /// for cells carrying extra data that has no effect on the following steps.
+    pub fn run_until_or_cycle_by<K: Hash + Eq>(
+        &mut self,
+        max_steps: Option<usize>,
+        predicate: impl Fn(&Grid<R::Cell>) -> bool,
+        key: impl Fn(&Grid<R::Cell>) -> K,
+    ) -> Outcome {
+        let limit = max_steps.map(|max| self.steps + max);
+        let mut seen: HashMap<K, usize> = HashMap::new();
+        seen.insert(key(&self.grid), self.steps);
+        while limit.is_none_or(|limit| self.steps < limit) {
+            self.step();
+            if predicate(&self.grid) {
+                return Outcome::Reached(self.steps);
+            }
+            let state = key(&self.grid);
+            if let Some(&start) = seen.get(&state) {
+                return Outcome::Cycled(Cycle {
+                    start,
+                    length: self.steps - start,
+                });
+            }
+            seen.insert(state, self.steps);
+        }
+        Outcome::StepLimit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Life;
+
+    impl LocalRule for Life {
+        type Cell = bool;
+
+        fn neighborhood(&self) -> Neighborhood {
+            Neighborhood::Moore
+        }
+
+        fn next(&self, _phase: usize, &alive: &bool, neighbors: &[&bool]) -> bool {
+            let count = neighbors.iter().filter(|&&&n| n).count();
+            count == 3 || (alive && count == 2)
+        }
+    }
+
+    fn life_grid(rows: &[&str], edge: Edge) -> Grid<bool> {
+        let cells = rows
+            .iter()
+            .map(|row| row.chars().map(|ch| ch == '#').collect())
+            .collect();
+        Grid::from_rows(cells, edge, false)
+    }
+
+    #[test]
+    fn blinker_cycle() {
+        let grid = life_grid(
+            &[".....", "..#..", "..#..", "..#..", "....."],
+            Edge::Bounded,
+        );
+        let mut automaton = Automaton::new(Life, grid);
+        let cycle = automaton.run_until_cycle(Some(10));
+        assert_eq!(
+            cycle,
+            Some(Cycle {
+                start: 0,
+                length: 2
+            })
+        );
+        assert_eq!(
+            automaton
+                .grid
+                .render(|&n| if n { '#' } else { '.' })
+                .lines()
+                .nth(2),
+            Some("..#..")
+        );
+    }
+
+    #[test]
+    fn glider_on_torus() {
+        let grid = life_grid(
+            &[".#....", "..#...", "###...", "......", "......", "......"],
+            Edge::Toroidal,
+        );
+        let mut automaton = Automaton::new(Life, grid.clone());
+        // glider moves by one cell diagonally every 4 steps
+        assert_eq!(automaton.run_until(Some(100), |g| *g == grid), Some(24));
+        assert_eq!(automaton.run_until_stable(Some(5)), None);
+    }
+
+    #[test]
+    fn infinite_growth() {
+        let grid = life_grid(&["##", "##"], Edge::Infinite);
+        let mut automaton = Automaton::new(Life, grid);
+        assert_eq!(automaton.run_until_stable(None), Some(1));
+        assert_eq!(automaton.grid.rows(), 4);
+        assert_eq!(automaton.grid.count(|&n| n), 4);
+        assert_eq!(Neighborhood::VonNeumann.radius(), 1);
+        assert_eq!(Neighborhood::Custom(vec![(0, -2), (1, 1)]).radius(), 2);
+    }
+}
@@ -1,139 +1,361 @@
-use std::collections::{HashMap, HashSet};
-
 mod utils;
 const DAY_ID: utils::DayIdType = 9;
 
 type Res = u32;
 type DepthType = u8;
 
+/// Heights stored row by row in single contiguous vector.
 #[derive(Debug)]
 struct DepthMap {
-    heights: HashMap<(i32, i32), DepthType>,
+    heights: Vec<DepthType>,
     rows: usize,
     columns: usize,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+enum MapError {
+    Empty,
+    JaggedRow {
+        row: usize,
+        len: usize,
+        expected: usize,
+    },
+    InvalidHeight {
+        row: usize,
+        col: usize,
+        ch: char,
+    },
+}
+
+impl TryFrom<&str> for DepthMap {
+    type Error = MapError;
+
+    fn try_from(data: &str) -> Result<Self, Self::Error> {
+        let columns = data.lines().next().map_or(0, |line| line.len());
+        if columns == 0 {
+            return Err(MapError::Empty);
+        }
+
+        let mut heights = Vec::with_capacity(data.len());
+        let mut rows = 0;
+        for (row, line) in data.lines().enumerate() {
+            if line.len() != columns {
+                return Err(MapError::JaggedRow {
+                    row,
+                    len: line.len(),
+                    expected: columns,
+                });
+            }
+            for (col, ch) in line.chars().enumerate() {
+                let height = ch
+                    .to_digit(10)
+                    .ok_or(MapError::InvalidHeight { row, col, ch })?;
+                heights.push(height as DepthType);
+            }
+            rows += 1;
+        }
+
+        Ok(DepthMap {
+            heights,
+            rows,
+            columns,
+        })
+    }
+}
+
 impl DepthMap {
+    /// Height at given position, `None` for positions beyond the map.
     pub fn get(&self, r: i32, c: i32) -> Option<DepthType> {
-        self.heights.get(&(r, c)).copied()
+        if r < 0 || c < 0 || r as usize >= self.rows || c as usize >= self.columns {
+            None
+        } else {
+            Some(self.heights[r as usize * self.columns + c as usize])
+        }
+    }
+
+    /// Positions of orthogonally adjacent locations, those beyond the map are left out.
+    pub fn neighbor_positions(&self, row: usize, col: usize) -> Vec<(usize, usize)> {
+        NEIGHBOR_OFFSETS
+            .iter()
+            .map(|(y, x)| (row as i32 + y, col as i32 + x))
+            .filter(|&(r, c)| self.get(r, c).is_some())
+            .map(|(r, c)| (r as usize, c as usize))
+            .collect()
     }
 
     pub fn neighbors_of(&self, row: usize, col: usize) -> Vec<DepthType> {
-        let mut neighbors = Vec::with_capacity(4);
-        const OFFSET: [(i32, i32); 4] = [(0, -1), (0, 1), (-1, 0), (1, 0)];
-        for (y, x) in OFFSET.iter() {
-            let r = row as i32 + y;
-            let c = col as i32 + x;
-            if let Some(&x) = self.heights.get(&(r, c)).or(Some(&9)) {
-                neighbors.push(x);
+        self.neighbor_positions(row, col)
+            .iter()
+            .map(|&(r, c)| self.heights[r * self.columns + c])
+            .collect()
+    }
+
+    /// Location lower than all of its adjacent locations, map edges don't count.
+    pub fn is_low_point(&self, row: usize, col: usize) -> bool {
+        let me = self.heights[row * self.columns + col];
+        self.neighbors_of(row, col).iter().all(|&x| x > me)
+    }
+
+    /// Splits the map into basins - areas of locations lower than 9, found by flood fill.
+    pub fn basins(&self) -> Basins {
+        let mut labels: Vec<Option<usize>> = vec![None; self.heights.len()];
+        let mut basins = vec![];
+
+        for start in 0..self.heights.len() {
+            if labels[start].is_some() || self.heights[start] == 9 {
+                continue;
             }
-        }
-        neighbors
-    }
-
-    pub fn get_basin_score(
-        &self,
-        visited: &mut HashSet<(usize, usize)>,
-        r: i32,
-        c: i32,
-        skip_direction: SkipDirection,
-    ) -> Res {
-        if r < 0 || (r > self.rows as i32 - 1) || c < 0 || (c > self.columns as i32 - 1) {
-            return 0;
+
+            let id = basins.len();
+            let mut basin = Basin {
+                id,
+                size: 0,
+                low_point: (start / self.columns, start % self.columns),
+            };
+            let mut lowest = self.heights[start];
+            let mut stack = vec![start];
+            labels[start] = Some(id);
+            while let Some(index) = stack.pop() {
+                let (r, c) = (index / self.columns, index % self.columns);
+                basin.size += 1;
+                if self.heights[index] < lowest
+                    || (self.heights[index] == lowest && (r, c) < basin.low_point)
+                {
+                    lowest = self.heights[index];
+                    basin.low_point = (r, c);
+                }
+                for (r1, c1) in self.neighbor_positions(r, c) {
+                    let next = r1 * self.columns + c1;
+                    if self.heights[next] < 9 && labels[next].is_none() {
+                        labels[next] = Some(id);
+                        stack.push(next);
+                    }
+                }
+            }
+            basins.push(basin);
         }
 
-        let me = self.get(r, c);
-        if me == Some(9) {
-            return 0;
+        Basins {
+            columns: self.columns,
+            labels,
+            basins,
         }
+    }
+}
 
-        let mut score = 0;
+const NEIGHBOR_OFFSETS: [(i32, i32); 4] = [(0, -1), (0, 1), (-1, 0), (1, 0)];
 
-        if skip_direction != SkipDirection::Left && me < self.get(r, c - 1) {
-            score += self.get_basin_score(visited, r, c - 1, SkipDirection::Right);
-        }
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Basin {
+    id: usize,
+    size: Res,
+    /// the lowest location of the basin, the first one in reading order if there are more
+    low_point: (usize, usize),
+}
 
-        if skip_direction != SkipDirection::Right && me < self.get(r, c + 1) {
-            score += self.get_basin_score(visited, r, c + 1, SkipDirection::Left);
-        }
+/// All basins of the map, with basin id of every location (`None` for those of height 9).
+#[derive(Debug)]
+struct Basins {
+    columns: usize,
+    labels: Vec<Option<usize>>,
+    basins: Vec<Basin>,
+}
 
-        if skip_direction != SkipDirection::Up && me < self.get(r - 1, c) {
-            score += self.get_basin_score(visited, r - 1, c, SkipDirection::Down);
-        }
+impl Basins {
+    /// Basins from the largest one, equally large ones ordered by id.
+    pub fn by_size(&self) -> Vec<&Basin> {
+        let mut sorted: Vec<&Basin> = self.basins.iter().collect();
+        sorted.sort_unstable_by_key(|b| (std::cmp::Reverse(b.size), b.id));
+        sorted
+    }
+
+    /// Up to `k` largest basins.
+    pub fn top(&self, k: usize) -> Vec<&Basin> {
+        let mut sorted = self.by_size();
+        sorted.truncate(k);
+        sorted
+    }
 
-        if skip_direction != SkipDirection::Down && me < self.get(r + 1, c) {
-            score += self.get_basin_score(visited, r + 1, c, SkipDirection::Up);
+    /// Product of sizes of `k` largest basins, `None` if there are less of them or on overflow.
+    pub fn top_product(&self, k: usize) -> Option<Res> {
+        let top = self.top(k);
+        if top.len() < k {
+            return None;
         }
+        top.iter()
+            .try_fold(1 as Res, |product, b| product.checked_mul(b.size))
+    }
 
-        visited.insert((r as usize, c as usize));
-        score
+    /// Map of basins with letter per each basin (repeating after 52 of them), `.` for height 9.
+    /// Optionally colored by ANSI terminal escape codes, low points highlighted.
+    pub fn render(&self, colored: bool) -> String {
+        const LETTERS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        let mut s = String::new();
+        for (index, label) in self.labels.iter().enumerate() {
+            let (r, c) = (index / self.columns, index % self.columns);
+            match label {
+                Some(id) => {
+                    let letter = LETTERS[id % LETTERS.len()] as char;
+                    if colored {
+                        let bold = if self.basins[*id].low_point == (r, c) {
+                            "1;"
+                        } else {
+                            ""
+                        };
+                        s += &format!("\x1b[{}{}m{}\x1b[0m", bold, 31 + id % 6, letter);
+                    } else {
+                        s.push(letter);
+                    }
+                }
+                None => s.push('.'),
+            }
+            if c == self.columns - 1 {
+                s.push('\n');
+            }
+        }
+        s
     }
 }
 
 fn parse_input(data: &str) -> DepthMap {
-    let mut heights = HashMap::new();
-
-    let mut rows = 0;
-    data.lines().enumerate().for_each(|(row, line)| {
-        rows += 1;
-        line.chars().enumerate().for_each(|(col, ch)| {
-            heights.insert(
-                (row as i32, col as i32),
-                ch.to_digit(10).unwrap() as DepthType,
-            );
-        });
-    });
-
-    let columns = data.lines().next().unwrap().len();
-
-    DepthMap {
-        heights,
-        rows,
-        columns,
-    }
+    DepthMap::try_from(data).unwrap()
 }
 
 fn solve_part1(data: &DepthMap) -> Res {
     let mut risk = 0;
     for r in 0..data.rows {
         for c in 0..data.columns {
-            let me = data.get(r as i32, c as i32).unwrap();
-            if data.neighbors_of(r, c).iter().filter(|&&x| x > me).count() == 4 {
-                risk += me as Res + 1;
+            if data.is_low_point(r, c) {
+                risk += data.get(r as i32, c as i32).unwrap() as Res + 1;
             }
         }
     }
     risk
 }
 
-#[derive(Eq, PartialEq)]
-enum SkipDirection {
-    None,
-    Left,
-    Right,
-    Up,
-    Down,
+fn solve_part2(data: &DepthMap) -> Res {
+    // maps with less than three basins have no answer
+    data.basins().top_product(3).unwrap_or(0)
 }
 
-fn solve_part2(data: &DepthMap) -> Res {
-    let mut basins: Vec<Res> = vec![];
+/// Run with `-- --map` to also print the colored map of basins.
+fn main() {
+    let data_str = utils::string_from_input(DAY_ID);
+    let data;
+    print_timed_result!("parse input", data = parse_input(&data_str));
+    print_timed_result!("part 1", solve_part1(&data));
+    print_timed_result!("part 2", solve_part2(&data));
 
-    for r in 0..data.rows {
-        for c in 0..data.columns {
-            let me = data.get(r as i32, c as i32).unwrap();
-            if data.neighbors_of(r, c).iter().all(|&x| x > me) {
-                let mut visited: HashSet<(usize, usize)> = HashSet::new();
-                data.get_basin_score(&mut visited, r as i32, c as i32, SkipDirection::None);
-                basins.push(visited.len() as Res);
+    if std::env::args().any(|arg| arg == "--map") {
+        print!("{}", data.basins().render(true));
+    }
+}
+
+generate_tests!(15, 1134);
+
+#[cfg(test)]
+mod basin_tests {
+    use super::*;
+
+    #[test]
+    fn sample_basins() {
+        let map = parse_input(&utils::string_from_sample(DAY_ID));
+        let basins = map.basins();
+        let found: Vec<(Res, (usize, usize))> = basins
+            .basins
+            .iter()
+            .map(|b| (b.size, b.low_point))
+            .collect();
+        assert_eq!(
+            found,
+            vec![(3, (0, 1)), (9, (0, 9)), (14, (2, 2)), (9, (4, 6))]
+        );
+        assert_eq!(
+            basins.render(false),
+            "aa...bbbbb\n\
+             a.ccc.b.bb\n\
+             .ccccc.d.b\n\
+             ccccc.ddd.\n\
+             .c...ddddd\n"
+        );
+    }
+
+    #[test]
+    fn plateau() {
+        // flat basin bottom - whole area counts, even if not growing strictly uphill
+        let map = parse_input("9999\n9119\n9129\n9999");
+        let basins = map.basins();
+        assert_eq!(basins.basins.len(), 1);
+        assert_eq!(basins.basins[0].size, 4);
+        assert_eq!(basins.basins[0].low_point, (1, 1));
+    }
+
+    #[test]
+    fn invalid_maps() {
+        assert_eq!(DepthMap::try_from("").unwrap_err(), MapError::Empty);
+        assert_eq!(
+            DepthMap::try_from("123\n45\n").unwrap_err(),
+            MapError::JaggedRow {
+                row: 1,
+                len: 2,
+                expected: 3
             }
-        }
+        );
+        assert_eq!(
+            DepthMap::try_from("123\n4x6\n").unwrap_err(),
+            MapError::InvalidHeight {
+                row: 1,
+                col: 1,
+                ch: 'x'
+            }
+        );
     }
 
-    let blen = basins.len();
-    basins.sort_unstable();
-    basins[blen - 1] * basins[blen - 2] * basins[blen - 3]
-}
+    #[test]
+    fn edges_and_large_map() {
+        // edges do not make a location low, only its existing neighbors do
+        let map = parse_input("9\n8");
+        assert!(!map.is_low_point(0, 0) && map.is_low_point(1, 0));
+        assert_eq!(map.get(2, 0), None);
+
+        let size = 1500;
+        let mut data = String::with_capacity(size * (size + 1));
+        for r in 0..size {
+            for c in 0..size {
+                let wall = r % 50 == 49 || c % 70 == 69;
+                data.push(if wall {
+                    '9'
+                } else {
+                    (b'0' + ((r * 7 + c * 3) % 9) as u8) as char
+                });
+            }
+            data.push('\n');
+        }
+        let map = parse_input(&data);
+        let basins = map.basins();
+        assert_eq!(basins.basins.len(), 30 * 22);
+        assert!(basins.basins.iter().all(|b| b.size <= 49 * 69));
+        let total: Res = basins.basins.iter().map(|b| b.size).sum();
+        let walls = map.heights.iter().filter(|&&h| h == 9).count();
+        assert_eq!(total as usize + walls, size * size);
+    }
 
-generate_main!();
+    #[test]
+    fn top_basins() {
+        let map = parse_input(&utils::string_from_sample(DAY_ID));
+        let basins = map.basins();
+        let sizes: Vec<Res> = basins.by_size().iter().map(|b| b.size).collect();
+        assert_eq!(sizes, vec![14, 9, 9, 3]);
+        // equally large basins keep their order by id
+        let ids: Vec<usize> = basins.top(3).iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![2, 1, 3]);
+        assert_eq!(basins.top(10).len(), 4);
+        assert_eq!(basins.top_product(1), Some(14));
+        assert_eq!(basins.top_product(4), Some(14 * 9 * 9 * 3));
+        assert_eq!(basins.top_product(5), None);
 
-generate_tests!(15, 1134);
+        let tiny = parse_input("19\n91");
+        assert_eq!(tiny.basins().top_product(2), Some(1));
+        assert_eq!(solve_part2(&tiny), 0);
+    }
+}
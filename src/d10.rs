@@ -7,112 +7,223 @@ fn parse_input(data: &str) -> Vec<&str> {
     data.lines().collect()
 }
 
-// fn is_incomplete(line: &str) -> bool {
-//     let openings = line.chars().filter(|&x| "<[({".contains(x)).count();
-//     let closings = line.chars().filter(|&x| ">])}".contains(x)).count();
-//     openings != closings
-// }
-
-fn is_opener(ch: char) -> bool {
-    "([{<".contains(ch)
+/// Outcome of checking single line, indices being char positions within the line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Diagnosis {
+    /// all chunks properly opened and closed
+    Ok,
+    /// chunk closed by closer of different pair
+    Corrupted {
+        index: usize,
+        expected: char,
+        found: char,
+    },
+    /// some chunks left open, closing them needs the missing suffix
+    Incomplete { missing: String },
+    /// closer without any chunk open
+    UnexpectedCloser { index: usize, found: char },
+    /// char being neither opener nor closer
+    InvalidChar { index: usize, found: char },
 }
 
-fn is_closer(ch: char) -> bool {
-    ")]}>".contains(ch)
+/// Suggested fix of broken line, scored the puzzle way.
+/// Score is None when the fix involves closer other than the default ones,
+/// or when it does not fit into Res.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Repair {
+    /// incomplete line with the missing closers appended
+    Completed {
+        line: String,
+        appended: String,
+        score: Option<Res>,
+    },
+    /// corrupted line with its first illegal closer replaced by the expected one
+    Replaced {
+        line: String,
+        index: usize,
+        found: char,
+        expected: char,
+        score: Option<Res>,
+    },
+    /// line with its closer without any chunk open removed, scored as illegal closer
+    Removed {
+        line: String,
+        index: usize,
+        found: char,
+        score: Option<Res>,
+    },
 }
 
-fn first_illegal(line: &str) -> Option<(usize, char)> {
-    let mut stack = vec![];
-    for (i, ch) in line.chars().enumerate() {
-        if is_opener(ch) {
-            stack.push(ch);
-        } else if is_closer(ch) {
-            let opener = stack.pop().unwrap();
-            if !does_match(opener, ch) {
-                return Some((i, ch));
-            }
+impl Repair {
+    pub fn line(&self) -> &str {
+        match self {
+            Repair::Completed { line, .. }
+            | Repair::Replaced { line, .. }
+            | Repair::Removed { line, .. } => line,
         }
     }
-    None
 }
 
-fn does_match(o: char, c: char) -> bool {
-    c == match o {
-        '(' => ')',
-        '[' => ']',
-        '{' => '}',
-        '<' => '>',
-        _ => {
-            return false;
-        }
+/// Checker of chunk syntax, for any set of (opener, closer) delimiter pairs.
+/// Pair with the same opener and closer (quotes) closes the chunk when it is the innermost open one,
+/// otherwise it opens new chunk.
+#[derive(Debug, Clone)]
+struct Checker {
+    pairs: Vec<(char, char)>,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self::new(&[('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')])
     }
 }
 
-fn solve_part1(data: &[&str]) -> Res {
-    let mut failed: Vec<char> = vec![];
-    for line in data.iter() {
-        if let Some(ch) = first_illegal(line) {
-            failed.push(ch.1);
-        };
+impl Checker {
+    /// Panics when some char is used by more than one pair.
+    pub fn new(pairs: &[(char, char)]) -> Self {
+        for (i, &(opener, closer)) in pairs.iter().enumerate() {
+            for &other in pairs[..i].iter() {
+                assert!(
+                    ![other.0, other.1].contains(&opener) && ![other.0, other.1].contains(&closer),
+                    "delimiter pairs {:?} and {:?} share a char",
+                    other,
+                    (opener, closer)
+                );
+            }
+        }
+        Self {
+            pairs: pairs.to_vec(),
+        }
     }
 
-    failed
-        .iter()
-        .map(|x| match x {
-            ')' => 3,
-            ']' => 57,
-            '}' => 1197,
-            '>' => 25137,
-            _ => unreachable!(),
-        })
-        .sum()
-}
+    fn closer_of(&self, opener: char) -> Option<char> {
+        self.pairs.iter().find(|p| p.0 == opener).map(|p| p.1)
+    }
+
+    fn is_closer(&self, ch: char) -> bool {
+        self.pairs.iter().any(|p| p.1 == ch)
+    }
 
-fn get_suffix(line: &str) -> Vec<char> {
-    let mut stack = vec![];
-    for ch in line.chars() {
-        if is_opener(ch) {
-            stack.push(ch);
-        } else if is_closer(ch) {
-            stack.pop();
+    /// Checks the line up to the first error.
+    pub fn check(&self, line: &str) -> Diagnosis {
+        // closers expected for the chunks open so far
+        let mut stack = vec![];
+        for (index, ch) in line.chars().enumerate() {
+            if stack.last() == Some(&ch) && self.closer_of(ch) == Some(ch) {
+                stack.pop();
+            } else if let Some(closer) = self.closer_of(ch) {
+                stack.push(closer);
+            } else if self.is_closer(ch) {
+                match stack.pop() {
+                    None => return Diagnosis::UnexpectedCloser { index, found: ch },
+                    Some(expected) if expected != ch => {
+                        return Diagnosis::Corrupted {
+                            index,
+                            expected,
+                            found: ch,
+                        }
+                    }
+                    Some(_) => {}
+                }
+            } else {
+                return Diagnosis::InvalidChar { index, found: ch };
+            }
         }
+
+        if stack.is_empty() {
+            Diagnosis::Ok
+        } else {
+            Diagnosis::Incomplete {
+                missing: stack.iter().rev().collect(),
+            }
+        }
+    }
+
+    /// Suggests minimal edit for incomplete, corrupted or unexpectedly closed line.
+    /// Fine lines and lines with invalid chars give None.
+    /// Fixing the first illegal closer does not guarantee the rest of line is fine.
+    pub fn repair(&self, line: &str) -> Option<Repair> {
+        match self.check(line) {
+            Diagnosis::Incomplete { missing } => Some(Repair::Completed {
+                line: format!("{}{}", line, missing),
+                score: score(&missing),
+                appended: missing,
+            }),
+            Diagnosis::Corrupted {
+                index,
+                expected,
+                found,
+            } => Some(Repair::Replaced {
+                line: line
+                    .chars()
+                    .enumerate()
+                    .map(|(i, ch)| if i == index { expected } else { ch })
+                    .collect(),
+                index,
+                found,
+                expected,
+                score: illegal_score(found),
+            }),
+            Diagnosis::UnexpectedCloser { index, found } => Some(Repair::Removed {
+                line: line
+                    .chars()
+                    .enumerate()
+                    .filter_map(|(i, ch)| (i != index).then_some(ch))
+                    .collect(),
+                index,
+                found,
+                score: illegal_score(found),
+            }),
+            Diagnosis::Ok | Diagnosis::InvalidChar { .. } => None,
+        }
+    }
+}
+
+fn illegal_score(ch: char) -> Option<Res> {
+    match ch {
+        ')' => Some(3),
+        ']' => Some(57),
+        '}' => Some(1197),
+        '>' => Some(25137),
+        _ => None,
     }
-    stack
-        .iter()
-        .map(|x| match x {
-            '(' => ')',
-            '[' => ']',
-            '{' => '}',
-            '<' => '>',
-            _ => unreachable!(),
+}
+
+fn solve_part1(data: &[&str]) -> Res {
+    let checker = Checker::default();
+    data.iter()
+        .filter_map(|line| match checker.check(line) {
+            Diagnosis::Corrupted { found, .. } | Diagnosis::UnexpectedCloser { found, .. } => {
+                illegal_score(found)
+            }
+            _ => None,
         })
-        .rev()
-        .collect()
+        .sum()
 }
 
-fn score(missing_suffix: &[char]) -> u64 {
-    let mut score = 0;
-    for ch in missing_suffix.iter() {
-        score = 5 * score
-            + match ch {
-                ')' => 1,
-                ']' => 2,
-                '}' => 3,
-                '>' => 4,
-                _ => unreachable!(),
-            };
-    }
-    score
+/// None for unknown closer, or for suffix too long to be scored within Res.
+fn score(missing_suffix: &str) -> Option<Res> {
+    let mut score: Res = 0;
+    for ch in missing_suffix.chars() {
+        let value = match ch {
+            ')' => 1,
+            ']' => 2,
+            '}' => 3,
+            '>' => 4,
+            _ => return None,
+        };
+        score = score.checked_mul(5)?.checked_add(value)?;
+    }
+    Some(score)
 }
 
 fn solve_part2(data: &[&str]) -> Res {
+    let checker = Checker::default();
     let mut scores = vec![];
     for line in data.iter() {
-        if first_illegal(line).is_some() {
-            continue;
-        };
-        let missing_suffix = get_suffix(line);
-        scores.push(score(&missing_suffix));
+        if let Diagnosis::Incomplete { missing } = checker.check(line) {
+            scores.push(score(&missing).unwrap());
+        }
     }
 
     scores.sort_unstable();
@@ -121,6 +232,252 @@ fn solve_part2(data: &[&str]) -> Res {
     scores[index]
 }
 
-generate_main!();
+/// Run with `-- --repair` to also print the suggested fix of every broken line.
+fn main() {
+    let data_str = utils::string_from_input(DAY_ID);
+    let data;
+    print_timed_result!("parse input", data = parse_input(&data_str));
+    print_timed_result!("part 1", solve_part1(&data));
+    print_timed_result!("part 2", solve_part2(&data));
+
+    if std::env::args().any(|arg| arg == "--repair") {
+        let checker = Checker::default();
+        let score_text = |score: Option<Res>| score.map_or("unscored".into(), |s| s.to_string());
+        for line in data.iter() {
+            match checker.repair(line) {
+                Some(repair @ Repair::Completed { score, .. }) => println!(
+                    "{}\n -> {} (completed, {})",
+                    line,
+                    repair.line(),
+                    score_text(score)
+                ),
+                Some(repair @ Repair::Replaced { index, score, .. }) => println!(
+                    "{}\n -> {} (replaced at {}, {})",
+                    line,
+                    repair.line(),
+                    index,
+                    score_text(score)
+                ),
+                Some(repair @ Repair::Removed { index, score, .. }) => println!(
+                    "{}\n -> {} (removed at {}, {})",
+                    line,
+                    repair.line(),
+                    index,
+                    score_text(score)
+                ),
+                None => {}
+            }
+        }
+    }
+}
 
 generate_tests!(26397, 288957);
+
+#[cfg(test)]
+mod checker_tests {
+    use super::*;
+
+    #[test]
+    fn diagnoses() {
+        let checker = Checker::default();
+        for (line, diagnosis) in [
+            ("([]){<>}", Diagnosis::Ok),
+            (
+                "{([(<{}[<>[]}>{[]{[(<()>",
+                Diagnosis::Corrupted {
+                    index: 12,
+                    expected: ']',
+                    found: '}',
+                },
+            ),
+            (
+                "[({(<(())[]>[[{[]{<()<>>",
+                Diagnosis::Incomplete {
+                    missing: "}}]])})]".into(),
+                },
+            ),
+            (
+                "()]",
+                Diagnosis::UnexpectedCloser {
+                    index: 2,
+                    found: ']',
+                },
+            ),
+            (
+                "(a)",
+                Diagnosis::InvalidChar {
+                    index: 1,
+                    found: 'a',
+                },
+            ),
+        ] {
+            assert_eq!(checker.check(line), diagnosis);
+        }
+    }
+
+    #[test]
+    fn custom_pairs() {
+        // quotes-like delimiters, where "<" would be invalid
+        let checker = Checker::new(&[('(', ')'), ('«', '»')]);
+        assert_eq!(checker.check("«()»"), Diagnosis::Ok);
+        assert_eq!(
+            checker.check("«(»"),
+            Diagnosis::Corrupted {
+                index: 2,
+                expected: ')',
+                found: '»',
+            }
+        );
+        assert_eq!(
+            checker.check("(<"),
+            Diagnosis::InvalidChar {
+                index: 1,
+                found: '<',
+            }
+        );
+        assert_eq!(
+            checker.check("««("),
+            Diagnosis::Incomplete {
+                missing: ")»»".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn symmetric_pairs() {
+        let checker = Checker::new(&[('(', ')'), ('"', '"'), ('|', '|')]);
+        // quote inside other chunk opens nested one instead of closing the outer quote
+        assert_eq!(
+            checker.check(r#""(|")"#),
+            Diagnosis::Corrupted {
+                index: 4,
+                expected: '"',
+                found: ')',
+            }
+        );
+        assert_eq!(checker.check(r#""(|""|)""#), Diagnosis::Ok);
+        assert_eq!(checker.check(r#"""||"#), Diagnosis::Ok);
+        assert_eq!(
+            checker.check(r#"(""#),
+            Diagnosis::Incomplete {
+                missing: r#"")"#.into(),
+            }
+        );
+        assert_eq!(
+            checker.check(r#"(")"#),
+            Diagnosis::Corrupted {
+                index: 2,
+                expected: '"',
+                found: ')',
+            }
+        );
+        assert_eq!(
+            checker.repair(r#"|(""#),
+            Some(Repair::Completed {
+                line: r#"|("")|"#.into(),
+                appended: r#"")|"#.into(),
+                score: None,
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "share a char")]
+    fn overlapping_pairs() {
+        Checker::new(&[('(', ')'), ('[', '(')]);
+    }
+
+    #[test]
+    #[should_panic(expected = "share a char")]
+    fn repeated_opener() {
+        Checker::new(&[('(', ')'), ('(', ']')]);
+    }
+
+    #[test]
+    fn repairs() {
+        let checker = Checker::default();
+        assert_eq!(
+            checker.repair("<{([{{}}[<[[[<>{}]]]>[]]"),
+            Some(Repair::Completed {
+                line: "<{([{{}}[<[[[<>{}]]]>[]]])}>".into(),
+                appended: "])}>".into(),
+                score: Some(294),
+            })
+        );
+        assert_eq!(
+            checker.repair("[<(<(<(<{}))><([]([]()"),
+            Some(Repair::Replaced {
+                line: "[<(<(<(<{}>)><([]([]()".into(),
+                index: 10,
+                found: ')',
+                expected: '>',
+                score: Some(3),
+            })
+        );
+        assert_eq!(checker.repair("[]"), None);
+        assert_eq!(checker.repair("[a]"), None);
+        assert_eq!(
+            checker.repair("()]<>"),
+            Some(Repair::Removed {
+                line: "()<>".into(),
+                index: 2,
+                found: ']',
+                score: Some(57),
+            })
+        );
+
+        // 30 closers score over 5^29, no longer fitting into u64
+        let opened = "(".repeat(27);
+        assert!(matches!(
+            checker.repair(&opened),
+            Some(Repair::Completed { score: Some(_), .. })
+        ));
+        let opened = "(".repeat(30);
+        assert_eq!(
+            checker.repair(&opened),
+            Some(Repair::Completed {
+                line: format!("{}{}", opened, ")".repeat(30)),
+                appended: ")".repeat(30),
+                score: None,
+            })
+        );
+
+        // every repaired incomplete line is fine
+        for line in parse_input(&utils::string_from_sample(DAY_ID)) {
+            if let Some(repair @ Repair::Completed { .. }) = checker.repair(line) {
+                assert_eq!(checker.check(repair.line()), Diagnosis::Ok);
+            }
+        }
+
+        // custom delimiters are repaired but not scored
+        let checker = Checker::new(&[('(', ')'), ('«', '»')]);
+        assert_eq!(
+            checker.repair("«"),
+            Some(Repair::Completed {
+                line: "«»".into(),
+                appended: "»".into(),
+                score: None,
+            })
+        );
+        assert_eq!(
+            checker.repair("«)"),
+            Some(Repair::Replaced {
+                line: "«»".into(),
+                index: 1,
+                found: ')',
+                expected: '»',
+                score: Some(3),
+            })
+        );
+        assert_eq!(
+            checker.repair("(»"),
+            Some(Repair::Replaced {
+                line: "()".into(),
+                index: 1,
+                found: '»',
+                expected: ')',
+                score: None,
+            })
+        );
+    }
+}
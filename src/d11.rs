@@ -1,9 +1,12 @@
+mod automaton;
 mod utils;
+use automaton::{Automaton, Edge, Grid, Neighborhood, Outcome, Rule};
+
 const DAY_ID: utils::DayIdType = 11;
 
 type Res = u32;
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 struct Octopus {
     energy: u8,
     flashed: bool,
@@ -11,57 +14,150 @@ struct Octopus {
 
 impl Octopus {
     pub fn charge(&mut self) {
-        self.energy += 1;
+        self.energy = self.energy.saturating_add(1);
     }
 }
 
-#[derive(Clone)]
-struct Field {
-    pub octopi: Vec<Vec<Octopus>>,
-    pub rows: i32,
-    pub cols: i32,
+type Field = Grid<Octopus>;
+
+/// Charging of all the octopi, with flashes cascading through the neighbors.
+/// Octopi that flashed in the last step keep their `flashed` mark until the next one.
+#[derive(Debug, Clone)]
+struct OctopusRule {
+    /// octopus flashes once its energy gets above this level
+    threshold: u8,
+    /// octopi charged by flash of their neighbor
+    neighborhood: Neighborhood,
+    /// energy level of octopus after it flashed
+    reset: u8,
 }
 
-impl Field {
-    pub fn iter_mut_octopi(&mut self) -> impl IntoIterator<Item = &mut Octopus> {
-        self.octopi.iter_mut().flatten()
+impl Default for OctopusRule {
+    fn default() -> Self {
+        Self {
+            threshold: 9,
+            neighborhood: Neighborhood::Moore,
+            reset: 0,
+        }
     }
+}
 
-    pub fn iter_coords(&self) -> impl IntoIterator<Item = (i32, i32)> {
-        (0..self.rows)
-            .map(|x| (0..self.cols).map(move |y| (x, y)))
-            .flatten()
-            .collect::<Vec<_>>()
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StepStats {
+    flashes: u32,
+    /// longest chain of flashes, each one triggered by the previous one
+    cascade_depth: usize,
+}
 
-    fn is_out(&self, r: i32, c: i32) -> bool {
-        r < 0 || r > self.rows - 1 || c < 0 || c > self.cols - 1
+impl OctopusRule {
+    /// Flashes the octopus if it is charged enough and did not flash yet in this step.
+    fn try_flash(&self, field: &mut Field, r: usize, c: usize) -> bool {
+        let octopus = field.get_mut(r, c);
+        if octopus.energy <= self.threshold || octopus.flashed {
+            return false;
+        }
+        octopus.flashed = true;
+        true
     }
 
-    fn charge(&mut self, r: i32, c: i32) {
-        if !self.is_out(r, c) {
-            let octopus = &mut self.octopi[r as usize][c as usize];
-            octopus.charge();
+    /// Flashes all the octopi charged enough, together with everything their flashes trigger,
+    /// depth first in the same order as plain recursion would, just on explicit stack.
+    /// Returns the longest chain of flashes, each one triggered by the previous one.
+    fn do_flashes(&self, field: &mut Field) -> usize {
+        let offsets = self.neighborhood.offsets();
+        // flashing octopus, its depth in the chain and index of its next neighbor offset
+        let mut stack: Vec<(usize, usize, usize, usize)> = vec![];
+        let mut max_depth = 0;
+
+        for (r, c) in field.coords().collect::<Vec<_>>() {
+            if !self.try_flash(field, r, c) {
+                continue;
+            }
+            stack.push((r, c, 1, 0));
+            max_depth = max_depth.max(1);
+
+            while let Some(frame) = stack.last_mut() {
+                let (r, c, depth, next) = *frame;
+                let Some(&(dr, dc)) = offsets.get(next) else {
+                    stack.pop();
+                    continue;
+                };
+                frame.3 += 1;
+                let Some((r1, c1)) = field.resolve((r as isize + dr, c as isize + dc)) else {
+                    continue;
+                };
+
+                field.get_mut(r1, c1).charge();
+                if self.try_flash(field, r1, c1) {
+                    stack.push((r1, c1, depth + 1, 0));
+                    max_depth = max_depth.max(depth + 1);
+                }
+            }
         }
+        max_depth
     }
 
-    pub fn do_flash(&mut self, r: i32, c: i32) {
-        if self.is_out(r, c) {
-            return;
+    pub fn step_with_stats(&self, field: &Field) -> (Field, StepStats) {
+        let mut field = field.clone();
+        for octo in field.cells_mut() {
+            octo.flashed = false;
+            octo.charge();
         }
 
-        let mut octopus = &mut self.octopi[r as usize][c as usize];
+        let cascade_depth = self.do_flashes(&mut field);
 
-        if octopus.energy <= 9 || octopus.flashed {
-            return;
+        for octo in field.cells_mut() {
+            if octo.flashed {
+                octo.energy = self.reset;
+            }
         }
-        octopus.flashed = true;
 
-        for (r1, c1) in neighbors_of(r, c) {
-            self.charge(r1, c1);
-            self.do_flash(r1, c1);
+        let stats = StepStats {
+            flashes: flashes(&field),
+            cascade_depth,
+        };
+        (field, stats)
+    }
+}
+
+impl Rule for OctopusRule {
+    type Cell = Octopus;
+
+    fn step(&self, field: &Field) -> Field {
+        self.step_with_stats(field).0
+    }
+}
+
+/// Statistics of multiple steps - per each step, and how many times each octopus flashed.
+#[derive(Debug, Clone)]
+struct RunStats {
+    steps: Vec<StepStats>,
+    histogram: Grid<u32>,
+}
+
+impl RunStats {
+    pub fn total_flashes(&self) -> u32 {
+        self.steps.iter().map(|s| s.flashes).sum()
+    }
+}
+
+fn simulate(rule: &OctopusRule, field: &Field, steps: usize) -> (Field, RunStats) {
+    let mut field = field.clone();
+    let mut stats = RunStats {
+        steps: Vec::with_capacity(steps),
+        histogram: Grid::new(field.rows(), field.cols(), field.edge(), 0),
+    };
+    for _ in 0..steps {
+        let (next, step_stats) = rule.step_with_stats(&field);
+        field = next;
+        stats.steps.push(step_stats);
+        for (r, c) in field.coords() {
+            if field.get(r, c).flashed {
+                *stats.histogram.get_mut(r, c) += 1;
+            }
         }
     }
+    (field, stats)
 }
 
 fn parse_input(data: &str) -> Field {
@@ -77,71 +173,130 @@ fn parse_input(data: &str) -> Field {
         })
         .collect();
 
-    let rows = data.lines().count() as i32;
-    let cols = data.lines().next().unwrap().len() as i32;
+    let outside = Octopus {
+        energy: 0,
+        flashed: false,
+    };
+    Grid::from_rows(octopi, Edge::Bounded, outside)
+}
+
+/// Number of octopi that flashed during the last step.
+fn flashes(field: &Field) -> u32 {
+    field.count(|octo| octo.flashed) as u32
+}
 
-    Field { octopi, rows, cols }
+fn solve_part1(field: &Field) -> Res {
+    let (_, stats) = simulate(&OctopusRule::default(), field, 100);
+    stats.total_flashes()
 }
 
-fn neighbors_of(r: i32, c: i32) -> Vec<(i32, i32)> {
-    vec![
-        (r - 1, c - 1),
-        (r - 1, c),
-        (r - 1, c + 1),
-        (r, c - 1),
-        (r, c + 1),
-        (r + 1, c - 1),
-        (r + 1, c),
-        (r + 1, c + 1),
-    ]
+/// Looks for the first step when all the octopi flash at once. Field states are remembered,
+/// so that getting into a loop without synchronization is detected, as there are finitely many
+/// states this always terminates - optionally `max_steps` cuts the search short.
+/// States are told apart by energies only, the `flashed` marks do not affect the next step.
+fn find_sync(rule: &OctopusRule, field: &Field, max_steps: Option<usize>) -> Outcome {
+    let mut automaton = Automaton::new(rule.clone(), field.clone());
+
+    let max_flashes = (field.rows() * field.cols()) as u32;
+    let energies = |field: &Field| field.cells().map(|octo| octo.energy).collect::<Vec<_>>();
+    automaton.run_until_or_cycle_by(max_steps, |field| flashes(field) == max_flashes, energies)
 }
 
-fn perform_step(field: &mut Field) -> u32 {
-    let mut flashes = 0;
-    for octo in field.iter_mut_octopi() {
-        octo.charge();
+fn solve_part2(field: &Field) -> Res {
+    match find_sync(&OctopusRule::default(), field, None) {
+        Outcome::Reached(step) => step as Res,
+        outcome => panic!("octopi never synchronize: {:?}", outcome),
     }
+}
 
-    for (r, c) in field.iter_coords() {
-        field.do_flash(r, c);
-    }
+generate_main!();
 
-    for octo in field.iter_mut_octopi() {
-        if octo.flashed {
-            octo.energy = 0;
-            flashes += 1;
-        }
-        octo.flashed = false;
-    }
+generate_tests!(1656, 195);
 
-    flashes
-}
+#[cfg(test)]
+mod simulation_tests {
+    use super::*;
+    use automaton::Cycle;
 
-fn solve_part1(field: &Field) -> Res {
-    let mut field = field.clone();
+    #[test]
+    fn sync_outcomes() {
+        let rule = OctopusRule::default();
+        assert_eq!(
+            find_sync(&rule, &parse_input("5"), None),
+            Outcome::Reached(5)
+        );
+        let sample = parse_input(&utils::string_from_sample(DAY_ID));
+        assert_eq!(find_sync(&rule, &sample, Some(194)), Outcome::StepLimit);
+        assert_eq!(find_sync(&rule, &sample, Some(195)), Outcome::Reached(195));
+    }
 
-    let mut flashes = 0;
-    for _ in 0..100 {
-        flashes += perform_step(&mut field);
+    #[test]
+    fn never_sync() {
+        let rule = OctopusRule::default();
+        for (data, start, length) in [("005", 0, 9), ("00\n23", 7, 8)] {
+            assert_eq!(
+                find_sync(&rule, &parse_input(data), None),
+                Outcome::Cycled(Cycle { start, length })
+            );
+        }
     }
 
-    flashes
-}
+    #[test]
+    fn step_stats() {
+        let rule = OctopusRule::default();
+        let sample = parse_input(&utils::string_from_sample(DAY_ID));
+        let (_, stats) = simulate(&rule, &sample, 10);
+        assert_eq!(stats.steps[0].flashes, 0);
+        assert_eq!(stats.steps[1].flashes, 35);
+        assert_eq!(stats.total_flashes(), 204);
+        assert_eq!(stats.histogram.cells().sum::<u32>(), 204);
 
-fn solve_part2(field: &Field) -> Res {
-    let mut field = field.clone();
+        for (data, depth) in [("9\n8\n7", 2), ("988", 3), ("1234", 0)] {
+            let (_, stats) = simulate(&rule, &parse_input(data), 1);
+            assert_eq!(stats.steps[0].cascade_depth, depth);
+        }
+    }
 
-    let max_flashes = field.rows * field.cols;
+    #[test]
+    fn custom_rules() {
+        // plus shaped neighborhood does not reach the opposite corner
+        let rule = OctopusRule {
+            neighborhood: Neighborhood::VonNeumann,
+            ..OctopusRule::default()
+        };
+        let (_, stats) = simulate(&rule, &parse_input("97\n78"), 1);
+        assert_eq!(stats.steps[0].flashes, 1);
+        let (_, stats) = simulate(&OctopusRule::default(), &parse_input("97\n78"), 1);
+        assert_eq!(stats.steps[0].flashes, 4);
 
-    for step in 1.. {
-        if perform_step(&mut field) == max_flashes as u32 {
-            return step;
+        let rule = OctopusRule {
+            threshold: 3,
+            reset: 2,
+            ..OctopusRule::default()
         };
+        let (field, stats) = simulate(&rule, &parse_input("3"), 3);
+        assert_eq!(*stats.histogram.get(0, 0), 2);
+        assert_eq!(field.get(0, 0).energy, 2);
     }
 
-    unreachable!();
+    #[test]
+    fn large_cascade() {
+        // single charged corner sets off the whole grid, in chain too deep for plain recursion
+        let size = 1000;
+        let mut field = Grid::new(
+            size,
+            size,
+            Edge::Bounded,
+            Octopus {
+                energy: 8,
+                flashed: false,
+            },
+        );
+        field.get_mut(0, 0).energy = 9;
+        let (field, stats) = simulate(&OctopusRule::default(), &field, 1);
+        assert_eq!(stats.steps[0].flashes as usize, size * size);
+        // depth first walk snakes through the rows, chaining all the octopi but one
+        assert_eq!(stats.steps[0].cascade_depth, size * size - 1);
+        assert!(field.cells().all(|octo| octo.energy == 0));
+    }
 }
-
-generate_main!();
-
-generate_tests!(1656, 195);
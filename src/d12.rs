@@ -1,80 +1,544 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 mod utils;
 const DAY_ID: utils::DayIdType = 12;
 
-type Adjacencies<'a> = HashMap<&'a str, Vec<&'a str>>;
-type Path<'a> = String;
+type CaveId = usize;
+type Res = u64;
 
-fn parse_input(data: &str) -> Adjacencies {
-    let mut map: HashMap<&str, Vec<&str>> = Default::default();
-    data.lines().for_each(|line| {
-        let v: Vec<&str> = line.split('-').collect();
-        map.entry(v[0]).or_insert_with(Vec::new).push(v[1]);
-        map.entry(v[1]).or_insert_with(Vec::new).push(v[0]);
-    });
+fn parse_input(data: &str) -> CaveGraph<'_> {
+    CaveGraph::from(data)
+}
+
+/// Caves interned to integer ids, small ones also numbered for packed visit counts.
+#[derive(Debug)]
+struct CaveGraph<'a> {
+    names: Vec<&'a str>,
+    neighbors: Vec<Vec<CaveId>>,
+    /// slot of every small cave in packed visit counts, None for big ones
+    small_slots: Vec<Option<u32>>,
+    start: CaveId,
+    end: CaveId,
+}
+
+impl<'a> From<&'a str> for CaveGraph<'a> {
+    fn from(data: &'a str) -> Self {
+        let edges: Vec<(&str, &str)> = data
+            .lines()
+            .map(|line| line.split_once('-').unwrap())
+            .collect();
+
+        let mut names: Vec<&str> = edges.iter().flat_map(|&(a, b)| [a, b]).collect();
+        // sorted for stable ids and path order
+        names.sort_unstable();
+        names.dedup();
+        let ids: HashMap<&str, CaveId> = names.iter().enumerate().map(|(i, &n)| (n, i)).collect();
+
+        let mut neighbors = vec![vec![]; names.len()];
+        for (a, b) in edges {
+            neighbors[ids[a]].push(ids[b]);
+            neighbors[ids[b]].push(ids[a]);
+        }
+
+        let mut small_count = 0;
+        let small_slots = names
+            .iter()
+            .map(|name| {
+                if name.chars().all(|ch| ch.is_ascii_lowercase()) {
+                    small_count += 1;
+                    Some(small_count - 1)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        Self {
+            start: ids["start"],
+            end: ids["end"],
+            names,
+            neighbors,
+            small_slots,
+        }
+    }
+}
+
+/// Rules for visiting caves on the way from start to end.
+/// Start is never entered again and end finishes the path, whatever the rules.
+#[derive(Debug, Clone)]
+struct VisitPolicy<'p> {
+    /// most visits of any single small cave
+    pub max_visits: u8,
+    /// how many small caves can be visited more than once
+    pub max_revisited: u8,
+    /// caves never entered
+    pub forbidden: Vec<&'p str>,
+    /// caves every path has to pass through
+    pub mandatory: Vec<&'p str>,
+}
+
+impl VisitPolicy<'_> {
+    /// Every small cave visited at most once.
+    pub fn single() -> Self {
+        Self {
+            max_visits: 1,
+            max_revisited: 0,
+            forbidden: vec![],
+            mandatory: vec![],
+        }
+    }
+
+    /// Every small cave visited at most once, except for one visited twice.
+    pub fn one_double() -> Self {
+        Self {
+            max_visits: 2,
+            max_revisited: 1,
+            ..Self::single()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum PolicyError {
+    UnknownCave(String),
+    InvalidMaxVisits(u8),
+    /// visit counts of all small caves do not fit the packed state
+    TooManySmallCaves {
+        count: usize,
+        limit: usize,
+    },
+    TooManyMandatory {
+        count: usize,
+        limit: usize,
+    },
+    /// big caves connected directly, so paths can bounce between them forever
+    InfinitePaths(String, String),
+}
 
-    map
+/// Visit policy resolved against particular graph.
+#[derive(Debug)]
+struct Constraints {
+    /// bits of single small cave visit count in the packed state
+    slot_bits: u32,
+    max_visits: u8,
+    max_revisited: u8,
+    forbidden: Vec<bool>,
+    mandatory: Vec<CaveId>,
+    /// bit of every mandatory cave, 0 for others
+    mandatory_bits: Vec<u64>,
 }
 
-fn path_to_string(path: &[&str]) -> String {
-    path.join("-")
+impl Constraints {
+    fn all_mandatory(&self) -> u64 {
+        self.mandatory_bits.iter().fold(0, |all, bit| all | bit)
+    }
 }
 
-fn traverse<'a>(
-    adjacencies: &'a Adjacencies,
-    path: &mut Vec<&'a str>,
-    allow_double: bool,
-    has_double: bool,
-    result: &mut HashSet<String>,
-) {
-    let current_node = path.iter().last().unwrap();
-    let neighbors = adjacencies.get(current_node).unwrap();
+impl<'a> CaveGraph<'a> {
+    fn id(&self, name: &str) -> Result<CaveId, PolicyError> {
+        self.names
+            .iter()
+            .position(|&n| n == name)
+            .ok_or_else(|| PolicyError::UnknownCave(name.to_string()))
+    }
 
-    for &node in neighbors.iter() {
-        if node == "start" {
-            continue;
+    fn resolve(&self, policy: &VisitPolicy) -> Result<Constraints, PolicyError> {
+        if policy.max_visits == 0 {
+            return Err(PolicyError::InvalidMaxVisits(policy.max_visits));
+        }
+        // visit counts of all small caves are packed into single number
+        let slot_bits = u8::BITS - policy.max_visits.leading_zeros();
+        let count = self.small_slots.iter().flatten().count();
+        let limit = (u128::BITS / slot_bits) as usize;
+        if count > limit {
+            return Err(PolicyError::TooManySmallCaves { count, limit });
         }
 
-        let mut my_double = has_double;
-        if node.to_lowercase() == node && path.contains(&node) {
-            if !allow_double || has_double {
+        let mut forbidden = vec![false; self.names.len()];
+        for name in policy.forbidden.iter() {
+            forbidden[self.id(name)?] = true;
+        }
+        let mandatory = policy
+            .mandatory
+            .iter()
+            .map(|name| self.id(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let limit = u64::BITS as usize;
+        if mandatory.len() > limit {
+            let count = mandatory.len();
+            return Err(PolicyError::TooManyMandatory { count, limit });
+        }
+        let mut mandatory_bits = vec![0; self.names.len()];
+        for (i, &cave) in mandatory.iter().enumerate() {
+            mandatory_bits[cave] = 1 << i;
+        }
+
+        // conservative, the adjacent big caves need not be reachable on the way to end
+        let is_open_big = |c: CaveId| !self.is_small(c) && !forbidden[c];
+        for (cave, neighbors) in self.neighbors.iter().enumerate() {
+            if !is_open_big(cave) {
                 continue;
-            } else {
-                my_double = true;
+            }
+            if let Some(&other) = neighbors.iter().find(|&&n| is_open_big(n)) {
+                let (a, b) = (self.names[cave], self.names[other]);
+                return Err(PolicyError::InfinitePaths(a.to_string(), b.to_string()));
             }
         }
 
-        path.push(node);
+        Ok(Constraints {
+            slot_bits,
+            max_visits: policy.max_visits,
+            max_revisited: policy.max_revisited,
+            forbidden,
+            mandatory,
+            mandatory_bits,
+        })
+    }
+
+    /// Counts paths from start to end allowed by the policy.
+    pub fn count_paths(&self, policy: &VisitPolicy) -> Result<Res, PolicyError> {
+        let rules = self.resolve(policy)?;
+        let mut memo = HashMap::new();
+        let seen = rules.mandatory_bits[self.start];
+        Ok(self.count_from(&rules, self.start, 0, 0, seen, &mut memo))
+    }
+
+    fn count_from(
+        &self,
+        rules: &Constraints,
+        cave: CaveId,
+        visits: u128,
+        revisited: u8,
+        seen: u64,
+        memo: &mut HashMap<(CaveId, u128, u8, u64), Res>,
+    ) -> Res {
+        if cave == self.end {
+            return (seen == rules.all_mandatory()) as Res;
+        }
+        let key = (cave, visits, revisited, seen);
+        if let Some(&count) = memo.get(&key) {
+            return count;
+        }
 
-        if node == "end" {
-            result.insert(path_to_string(path));
-        } else {
-            traverse(adjacencies, path, allow_double, my_double, result);
+        let mut count = 0;
+        for &next in self.neighbors[cave].iter() {
+            if next == self.start || rules.forbidden[next] {
+                continue;
+            }
+            let seen = seen | rules.mandatory_bits[next];
+            count += match self.small_slots[next] {
+                None => self.count_from(rules, next, visits, revisited, seen, memo),
+                Some(slot) => {
+                    let shift = rules.slot_bits * slot;
+                    let n = ((visits >> shift) & ((1 << rules.slot_bits) - 1)) as u8;
+                    if n >= rules.max_visits || (n == 1 && revisited >= rules.max_revisited) {
+                        0
+                    } else {
+                        let visits = visits + (1 << shift);
+                        let revisited = revisited + (n == 1) as u8;
+                        self.count_from(rules, next, visits, revisited, seen, memo)
+                    }
+                }
+            };
         }
 
-        path.pop();
+        memo.insert(key, count);
+        count
     }
-}
 
-fn solve_part1(adjacencies: &Adjacencies) -> usize {
-    let mut path: Vec<&str> = vec!["start"];
-    let mut results: HashSet<Path> = HashSet::new();
+    fn is_small(&self, cave: CaveId) -> bool {
+        self.small_slots[cave].is_some()
+    }
+
+    /// Graphviz DOT description, small caves as ellipses and big ones as boxes,
+    /// with start and end filled.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("graph caves {\n");
+        for (id, name) in self.names.iter().enumerate() {
+            let shape = if self.is_small(id) { "ellipse" } else { "box" };
+            let fill = match id {
+                _ if id == self.start => ", style=filled, fillcolor=palegreen",
+                _ if id == self.end => ", style=filled, fillcolor=lightcoral",
+                _ => "",
+            };
+            dot.push_str(&format!("    \"{}\" [shape={}{}];\n", name, shape, fill));
+        }
+        for (id, neighbors) in self.neighbors.iter().enumerate() {
+            // every edge is stored in both directions, written once
+            for &other in neighbors.iter().filter(|&&other| other > id) {
+                let (a, b) = (self.names[id], self.names[other]);
+                dot.push_str(&format!("    \"{}\" -- \"{}\";\n", a, b));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
 
-    traverse(adjacencies, &mut path, false, false, &mut results);
+    /// Lazily enumerates the paths counted by `count_paths`.
+    pub fn paths(&self, policy: &VisitPolicy) -> Result<Paths<'_, 'a>, PolicyError> {
+        let mut visits = vec![0; self.names.len()];
+        visits[self.start] = 1;
+        Ok(Paths {
+            caves: self,
+            rules: self.resolve(policy)?,
+            path: vec![(self.start, 0)],
+            visits,
+            revisited: 0,
+        })
+    }
+}
 
-    results.len()
+/// Depth first path enumeration with explicit stack.
+struct Paths<'c, 'a> {
+    caves: &'c CaveGraph<'a>,
+    rules: Constraints,
+    /// caves on the path with index of next neighbor to try
+    path: Vec<(CaveId, usize)>,
+    visits: Vec<u8>,
+    revisited: u8,
 }
 
-fn solve_part2(adjacencies: &Adjacencies) -> usize {
-    let mut path: Vec<&str> = vec!["start"];
-    let mut results: HashSet<Path> = HashSet::new();
+impl<'a> Iterator for Paths<'_, 'a> {
+    type Item = Vec<&'a str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let caves = self.caves;
+        let rules = &self.rules;
+        loop {
+            let (cave, next_index) = self.path.last_mut()?;
+            let Some(&next) = caves.neighbors[*cave].get(*next_index) else {
+                let (cave, _) = self.path.pop().unwrap();
+                if caves.is_small(cave) && self.visits[cave] == 2 {
+                    self.revisited -= 1;
+                }
+                self.visits[cave] -= 1;
+                continue;
+            };
+            *next_index += 1;
+
+            if next == caves.start || rules.forbidden[next] {
+                continue;
+            }
+            if next == caves.end {
+                if rules
+                    .mandatory
+                    .iter()
+                    .any(|&c| c != next && self.visits[c] == 0)
+                {
+                    continue;
+                }
+                let mut path: Vec<&str> = self.path.iter().map(|&(c, _)| caves.names[c]).collect();
+                path.push(caves.names[next]);
+                return Some(path);
+            }
+            if caves.is_small(next) {
+                let n = self.visits[next];
+                if n >= rules.max_visits || (n == 1 && self.revisited >= rules.max_revisited) {
+                    continue;
+                }
+                if n == 1 {
+                    self.revisited += 1;
+                }
+            }
+            self.visits[next] += 1;
+            self.path.push((next, 0));
+        }
+    }
+}
 
-    traverse(adjacencies, &mut path, true, false, &mut results);
+fn solve_part1(caves: &CaveGraph) -> Res {
+    caves.count_paths(&VisitPolicy::single()).unwrap()
+}
 
-    results.len()
+fn solve_part2(caves: &CaveGraph) -> Res {
+    caves.count_paths(&VisitPolicy::one_double()).unwrap()
 }
 
-generate_main!();
+/// Run with `-- --dot <file>` to also write the graph in Graphviz format,
+/// and with `-- --list-paths` to print the paths of both parts.
+fn main() {
+    let data_str = utils::string_from_input(DAY_ID);
+    let data;
+    print_timed_result!("parse input", data = parse_input(&data_str));
+    print_timed_result!("part 1", solve_part1(&data));
+    print_timed_result!("part 2", solve_part2(&data));
+
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == "--dot") {
+        let path = args.get(i + 1).expect("missing file after --dot");
+        std::fs::write(path, data.to_dot()).unwrap();
+    }
+    if args.iter().any(|arg| arg == "--list-paths") {
+        for (part, policy) in [(1, VisitPolicy::single()), (2, VisitPolicy::one_double())] {
+            println!("part {} paths:", part);
+            for path in data.paths(&policy).unwrap() {
+                println!("{}", path.join(","));
+            }
+        }
+    }
+}
 
 generate_tests!(10, 36);
+
+#[cfg(test)]
+mod caves_tests {
+    use super::*;
+
+    const SLIGHTLY_LARGER: &str =
+        "dc-end\nHN-start\nstart-kj\ndc-start\ndc-HN\nLN-dc\nHN-end\nkj-sa\nkj-HN\nkj-dc";
+
+    fn policies<'p>(forbidden: &'p str, mandatory: &'p str) -> Vec<VisitPolicy<'p>> {
+        vec![
+            VisitPolicy::single(),
+            VisitPolicy::one_double(),
+            VisitPolicy {
+                max_visits: 3,
+                max_revisited: 2,
+                ..VisitPolicy::single()
+            },
+            VisitPolicy {
+                forbidden: vec![forbidden],
+                ..VisitPolicy::one_double()
+            },
+            VisitPolicy {
+                mandatory: vec![mandatory],
+                ..VisitPolicy::one_double()
+            },
+        ]
+    }
+
+    #[test]
+    fn larger_examples() {
+        let caves = parse_input(SLIGHTLY_LARGER);
+        assert_eq!(caves.count_paths(&VisitPolicy::single()), Ok(19));
+        assert_eq!(caves.count_paths(&VisitPolicy::one_double()), Ok(103));
+    }
+
+    #[test]
+    fn paths_match_counts() {
+        for (data, forbidden, mandatory) in [
+            (utils::string_from_sample(DAY_ID), "A", "b"),
+            (SLIGHTLY_LARGER.into(), "dc", "kj"),
+        ] {
+            let caves = parse_input(&data);
+            for policy in policies(forbidden, mandatory) {
+                let paths: Vec<Vec<&str>> = caves.paths(&policy).unwrap().collect();
+                assert_eq!(Ok(paths.len() as Res), caves.count_paths(&policy));
+                let mut unique = paths.clone();
+                unique.sort();
+                unique.dedup();
+                assert_eq!(unique.len(), paths.len());
+            }
+        }
+
+        let data = utils::string_from_sample(DAY_ID);
+        let caves = parse_input(&data);
+        assert!(caves
+            .paths(&VisitPolicy::single())
+            .unwrap()
+            .any(|path| path == ["start", "A", "b", "A", "c", "A", "end"]));
+    }
+
+    #[test]
+    fn dot_export() {
+        let data = utils::string_from_sample(DAY_ID);
+        let dot = parse_input(&data).to_dot();
+        assert!(dot.starts_with("graph caves {\n"));
+        assert!(dot.contains("\"A\" [shape=box];"));
+        assert!(dot.contains("\"b\" [shape=ellipse];"));
+        assert!(dot.contains("\"start\" [shape=ellipse, style=filled, fillcolor=palegreen];"));
+        assert!(dot.contains("\"end\" [shape=ellipse, style=filled, fillcolor=lightcoral];"));
+        assert_eq!(dot.matches(" -- ").count(), data.lines().count());
+    }
+
+    #[test]
+    fn policy_rules() {
+        let data = utils::string_from_sample(DAY_ID);
+        let caves = parse_input(&data);
+        let all: Vec<Vec<&str>> = caves.paths(&VisitPolicy::single()).unwrap().collect();
+        let with_c = all.iter().filter(|path| path.contains(&"c")).count() as Res;
+
+        let mandatory = VisitPolicy {
+            mandatory: vec!["c"],
+            ..VisitPolicy::single()
+        };
+        assert_eq!(caves.count_paths(&mandatory), Ok(with_c));
+        let forbidden = VisitPolicy {
+            forbidden: vec!["c"],
+            ..VisitPolicy::single()
+        };
+        assert_eq!(caves.count_paths(&forbidden), Ok(10 - with_c));
+
+        // a third visit of the revisited cave adds paths to the part 2 ones
+        let triple = VisitPolicy {
+            max_visits: 3,
+            max_revisited: 1,
+            ..VisitPolicy::single()
+        };
+        assert!(caves.count_paths(&triple).unwrap() > 36);
+
+        let unknown = VisitPolicy {
+            mandatory: vec!["x"],
+            ..VisitPolicy::single()
+        };
+        assert_eq!(
+            caves.count_paths(&unknown),
+            Err(PolicyError::UnknownCave("x".into()))
+        );
+        let zero = VisitPolicy {
+            max_visits: 0,
+            ..VisitPolicy::single()
+        };
+        assert_eq!(
+            caves.count_paths(&zero).err(),
+            Some(PolicyError::InvalidMaxVisits(0))
+        );
+    }
+
+    #[test]
+    fn policy_limits() {
+        // start, end and 70 small caves in between on single chain
+        let names: Vec<String> = (0..70u8)
+            .map(|i| format!("{}{}", (b'a' + i / 26) as char, (b'a' + i % 26) as char))
+            .collect();
+        let mut chain = vec!["start"];
+        chain.extend(names.iter().map(|name| name.as_str()));
+        chain.push("end");
+        let data: Vec<String> = chain.windows(2).map(|w| w.join("-")).collect();
+        let data = data.join("\n");
+        let caves = parse_input(&data);
+
+        assert_eq!(caves.count_paths(&VisitPolicy::single()), Ok(1));
+        assert_eq!(
+            caves.count_paths(&VisitPolicy::one_double()),
+            Err(PolicyError::TooManySmallCaves {
+                count: 72,
+                limit: 64,
+            })
+        );
+        let all_mandatory = VisitPolicy {
+            mandatory: chain[1..].to_vec(),
+            ..VisitPolicy::single()
+        };
+        assert_eq!(
+            caves.count_paths(&all_mandatory),
+            Err(PolicyError::TooManyMandatory {
+                count: 71,
+                limit: 64,
+            })
+        );
+    }
+
+    #[test]
+    fn infinite_paths() {
+        let caves = parse_input("start-A\nA-B\nB-end\nA-c\nc-end");
+        assert_eq!(
+            caves.count_paths(&VisitPolicy::single()),
+            Err(PolicyError::InfinitePaths("A".into(), "B".into()))
+        );
+        let without_b = VisitPolicy {
+            forbidden: vec!["B"],
+            ..VisitPolicy::single()
+        };
+        assert_eq!(caves.count_paths(&without_b), Ok(1));
+    }
+}
@@ -3,7 +3,7 @@ use std::collections::BTreeSet;
 mod utils;
 const DAY_ID: utils::DayIdType = 13;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum Fold {
     Row(usize),
     Column(usize),
@@ -11,17 +11,28 @@ enum Fold {
 
 type DotsT = BTreeSet<(usize, usize)>;
 
+/// Paper sized to fit all dots, unless folded to smaller size.
+/// As folds are made along the middle of the paper, the input paper is also
+/// large enough for the first fold in each direction to halve it.
 #[derive(Debug, Clone)]
 struct TransparentPaper {
     dots: DotsT,
-    rows: usize,
-    cols: usize,
+    width: usize,
+    height: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum FoldError {
+    /// fold line not crossing the paper
+    BeyondPaper { fold: Fold, size: usize },
+    /// dot would be lost in the crease
+    DotOnFoldLine { fold: Fold, dot: (usize, usize) },
 }
 
 fn parse_input(data: &str) -> (TransparentPaper, Vec<Fold>) {
     let mut dots = BTreeSet::new();
-    let mut cols = 0;
-    let mut rows = 0;
+    let mut width = 0;
+    let mut height = 0;
     let mut folds: Vec<Fold> = Vec::new();
 
     for line in data.lines() {
@@ -29,11 +40,16 @@ fn parse_input(data: &str) -> (TransparentPaper, Vec<Fold>) {
             let (x, y) = line.split_once(',').unwrap();
             let x = x.parse::<usize>().unwrap();
             let y = y.parse::<usize>().unwrap();
-            cols = cols.max(x);
-            rows = rows.max(y);
+            width = width.max(x + 1);
+            height = height.max(y + 1);
             dots.insert((x, y));
         } else if line.contains('=') {
-            let v = line.split('=').last().unwrap().parse::<usize>().unwrap();
+            let v = line
+                .split('=')
+                .next_back()
+                .unwrap()
+                .parse::<usize>()
+                .unwrap();
             folds.push(if line.contains("x=") {
                 Fold::Column(v)
             } else {
@@ -42,52 +58,86 @@ fn parse_input(data: &str) -> (TransparentPaper, Vec<Fold>) {
         }
     }
 
-    (TransparentPaper { dots, rows, cols }, folds)
+    // empty rows (columns) past the last dot still belong to the paper
+    if let Some(f) = folds.iter().find_map(|fold| match fold {
+        Fold::Column(f) => Some(f),
+        Fold::Row(_) => None,
+    }) {
+        width = width.max(2 * f + 1);
+    }
+    if let Some(f) = folds.iter().find_map(|fold| match fold {
+        Fold::Row(f) => Some(f),
+        Fold::Column(_) => None,
+    }) {
+        height = height.max(2 * f + 1);
+    }
+
+    (
+        TransparentPaper {
+            dots,
+            width,
+            height,
+        },
+        folds,
+    )
 }
 
 impl TransparentPaper {
-    fn fold(&mut self, folds: &[Fold]) {
-        for fold in folds.iter() {
-            let mut next_dots = BTreeSet::new();
-            for &(x, y) in self.dots.iter() {
-                match *fold {
-                    Fold::Column(f) => match x.cmp(&f) {
-                        std::cmp::Ordering::Less => {
-                            next_dots.insert((x, y));
-                        }
-                        std::cmp::Ordering::Equal => {}
-                        std::cmp::Ordering::Greater => {
-                            next_dots.insert((f - (x - f), y));
-                        }
-                    },
-                    Fold::Row(f) => match y.cmp(&f) {
-                        std::cmp::Ordering::Less => {
-                            next_dots.insert((x, y));
-                        }
-                        std::cmp::Ordering::Equal => {}
-                        std::cmp::Ordering::Greater => {
-                            next_dots.insert((x, f - (y - f)));
-                        }
-                    },
-                }
-            }
-            self.dots = next_dots;
-            match *fold {
-                Fold::Column(f) => {
-                    self.cols = f - 1;
-                }
-                Fold::Row(f) => {
-                    self.rows = f - 1;
-                }
+    /// Applies the folds in order, stopping at the first invalid one.
+    fn fold(&mut self, folds: &[Fold]) -> Result<(), FoldError> {
+        folds.iter().try_for_each(|&fold| self.fold_once(fold))
+    }
+
+    /// Folds the bottom (right) part up (left), paper unchanged on error.
+    /// When the folded part is the larger one, it sticks out past the top (left) edge,
+    /// so all the dots are shifted to keep the coordinates non-negative.
+    fn fold_once(&mut self, fold: Fold) -> Result<(), FoldError> {
+        let (line, size) = match fold {
+            Fold::Column(f) => (f, self.width),
+            Fold::Row(f) => (f, self.height),
+        };
+        if line >= size {
+            return Err(FoldError::BeyondPaper { fold, size });
+        }
+        let coord = |&(x, y): &(usize, usize)| match fold {
+            Fold::Column(_) => x,
+            Fold::Row(_) => y,
+        };
+        if let Some(&dot) = self.dots.iter().find(|dot| coord(dot) == line) {
+            return Err(FoldError::DotOnFoldLine { fold, dot });
+        }
+
+        let kept = line;
+        let folded = size - line - 1;
+        let shift = folded.saturating_sub(kept);
+        let moved = |v: usize| {
+            if v < line {
+                v + shift
+            } else {
+                shift + 2 * line - v
             }
+        };
+
+        self.dots = self
+            .dots
+            .iter()
+            .map(|&(x, y)| match fold {
+                Fold::Column(_) => (moved(x), y),
+                Fold::Row(_) => (x, moved(y)),
+            })
+            .collect();
+        match fold {
+            Fold::Column(_) => self.width = kept.max(folded),
+            Fold::Row(_) => self.height = kept.max(folded),
         }
+        Ok(())
     }
 
     fn to_nice_strings(&self) -> Vec<String> {
         let mut strs = Vec::new();
-        for r in 0..=self.rows {
+        for r in 0..self.height {
             let mut s = String::new();
-            for c in 0..=self.cols {
+            for c in 0..self.width {
                 if self.dots.contains(&(c, r)) {
                     s.push('\u{2588}');
                 } else {
@@ -99,6 +149,7 @@ impl TransparentPaper {
         strs
     }
 
+    #[allow(dead_code)]
     fn debug_print(&self) {
         for line in self.to_nice_strings().iter() {
             println!("{}", line);
@@ -106,21 +157,229 @@ impl TransparentPaper {
     }
 }
 
+/// Glyphs of the 4x6 block font, letters are separated by single empty column.
+/// The 5 columns wide 'Y' does not fit the grid, so it is read as unknown glyph.
+const FONT: [(char, [&str; 6]); 17] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 6;
+
+/// Reads the letters from rendered paper, any char but '.' being dot.
+/// Unknown glyphs are read as '?'.
+fn recognize_letters(lines: &[String]) -> String {
+    if lines.len() < GLYPH_HEIGHT {
+        return "?".into();
+    }
+    let rows: Vec<Vec<bool>> = lines[..GLYPH_HEIGHT]
+        .iter()
+        .map(|line| line.chars().map(|ch| ch != '.').collect())
+        .collect();
+    let width = rows[0].len();
+
+    (0..width.div_ceil(GLYPH_WIDTH + 1))
+        .map(|i| {
+            let left = i * (GLYPH_WIDTH + 1);
+            let is_dot = |r: usize, c: usize| rows[r].get(left + c).copied().unwrap_or(false);
+            FONT.iter()
+                .find(|(_, glyph)| {
+                    glyph.iter().enumerate().all(|(r, line)| {
+                        line.chars()
+                            .enumerate()
+                            .all(|(c, ch)| (ch == '#') == is_dot(r, c))
+                    })
+                })
+                .map_or('?', |&(letter, _)| letter)
+        })
+        .collect()
+}
+
 fn solve_part1((paper, folds): &(TransparentPaper, Vec<Fold>)) -> usize {
     let mut paper = paper.clone();
-    paper.fold(&folds[..1]);
+    paper.fold(&folds[..1]).unwrap();
     paper.dots.len()
 }
 
-fn solve_part2((paper, folds): &(TransparentPaper, Vec<Fold>)) -> usize {
+fn solve_part2((paper, folds): &(TransparentPaper, Vec<Fold>)) -> String {
     let mut paper = paper.clone();
-    paper.fold(folds);
+    paper.fold(folds).unwrap();
+    recognize_letters(&paper.to_nice_strings())
+}
+
+/// Run with `-- --paper` to also print the folded paper.
+fn main() {
+    let data_str = utils::string_from_input(DAY_ID);
+    let data;
+    print_timed_result!("parse input", data = parse_input(&data_str));
+    print_timed_result!("part 1", solve_part1(&data));
+    print_timed_result!("part 2", solve_part2(&data));
+
+    if std::env::args().any(|arg| arg == "--paper") {
+        let (paper, folds) = &data;
+        let mut paper = paper.clone();
+        paper.fold(folds).unwrap();
+        paper.debug_print();
+    }
+}
+
+// sample folds into a square, which is no letter
+generate_tests!(17, "?");
 
-    println!("\n-------- Part 2 output --------");
-    paper.debug_print();
-    0
+#[cfg(test)]
+mod ocr_tests {
+    use super::*;
+
+    // part 2 answer, as printed by `--paper`
+    const PAPER: [&str; 6] = [
+        "█....█..█.███..████.███..███..███..█..█.",
+        "█....█.█..█..█.█....█..█.█..█.█..█.█.█..",
+        "█....██...█..█.███..███..█..█.█..█.██...",
+        "█....█.█..███..█....█..█.███..███..█.█..",
+        "█....█.█..█.█..█....█..█.█....█.█..█.█..",
+        "████.█..█.█..█.████.███..█....█..█.█..█.",
+    ];
+
+    fn lines(bitmap: &[&str]) -> Vec<String> {
+        bitmap.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn letters() {
+        assert_eq!(recognize_letters(&lines(&PAPER)), "LKREBPRK");
+        let bitmap = [
+            "#..#.####..##...##...##..#..#",
+            "#..#....#.#..#.#..#.#..#.#..#",
+            "####...#..#....#..#.#....#..#",
+            "#..#..#...#.##.####.#....#..#",
+            "#..#.#....#..#.#..#.#..#.#..#",
+            "#..#.####..###.#..#..##...##.",
+        ];
+        assert_eq!(recognize_letters(&lines(&bitmap)), "HZGACU");
+    }
+
+    #[test]
+    fn folded_paper() {
+        // "F" written as is, "L" next to it upside down below the fold line
+        let mut data = String::new();
+        for (letter, left, mirrored) in [('F', 0, false), ('L', 5, true)] {
+            let glyph = FONT.iter().find(|(l, _)| *l == letter).unwrap().1;
+            for (r, line) in glyph.iter().enumerate() {
+                for (c, _) in line.match_indices('#') {
+                    let y = if mirrored { 12 - r } else { r };
+                    data.push_str(&format!("{},{}\n", left + c, y));
+                }
+            }
+        }
+        data.push_str("\nfold along y=6\n");
+
+        let (mut paper, folds) = parse_input(&data);
+        paper.fold(&folds).unwrap();
+        assert_eq!(recognize_letters(&paper.to_nice_strings()), "FL");
+    }
+
+    #[test]
+    fn unknown_glyphs() {
+        let mut paper = lines(&PAPER);
+        paper[0] = paper[0].replacen('.', "\u{2588}", 1);
+        assert_eq!(recognize_letters(&paper), "?KREBPRK");
+        assert_eq!(recognize_letters(&paper[..3]), "?");
+
+        // 5 columns wide 'Y' matches no glyph of the font
+        let wide_y = ["#...#", "#...#", ".#.#.", "..#..", "..#..", "..#.."];
+        assert_eq!(recognize_letters(&lines(&wide_y)), "?");
+    }
 }
 
-generate_main!();
+#[cfg(test)]
+mod fold_tests {
+    use super::*;
 
-generate_tests!(17, 0);
+    fn dots(paper: &TransparentPaper) -> Vec<(usize, usize)> {
+        paper.dots.iter().copied().collect()
+    }
+
+    #[test]
+    fn sample_sizes() {
+        let (mut paper, folds) = parse_input(&utils::string_from_sample(DAY_ID));
+        assert_eq!((paper.width, paper.height), (11, 15));
+        paper.fold(&folds[..1]).unwrap();
+        assert_eq!((paper.width, paper.height), (11, 7));
+        paper.fold(&folds[1..]).unwrap();
+        assert_eq!((paper.width, paper.height), (5, 7));
+        assert_eq!(paper.to_nice_strings()[0], "\u{2588}".repeat(5));
+    }
+
+    #[test]
+    fn asymmetric_folds() {
+        // larger right part sticks out on the left
+        let (mut paper, _) = parse_input("0,0\n5,0\n0,1");
+        paper.fold(&[Fold::Column(1)]).unwrap();
+        assert_eq!((paper.width, paper.height), (4, 2));
+        assert_eq!(dots(&paper), [(0, 0), (3, 0), (3, 1)]);
+
+        // folding along the very first column used to underflow
+        let (mut paper, _) = parse_input("1,0\n2,1");
+        paper.fold(&[Fold::Column(0)]).unwrap();
+        assert_eq!((paper.width, paper.height), (2, 2));
+        assert_eq!(dots(&paper), [(0, 1), (1, 0)]);
+
+        // smaller bottom part keeps the size of the top one
+        let (mut paper, _) = parse_input("0,0\n0,4");
+        paper.fold(&[Fold::Row(3)]).unwrap();
+        assert_eq!((paper.width, paper.height), (1, 3));
+        assert_eq!(dots(&paper), [(0, 0), (0, 2)]);
+    }
+
+    #[test]
+    fn size_from_folds() {
+        // dots alone would make the paper just 2 rows high
+        let (mut paper, folds) = parse_input("0,0\n0,1\n\nfold along y=5\nfold along y=2");
+        assert_eq!((paper.width, paper.height), (1, 11));
+        paper.fold(&folds).unwrap();
+        assert_eq!((paper.width, paper.height), (1, 2));
+        assert_eq!(dots(&paper), [(0, 0), (0, 1)]);
+
+        // only the first fold in each direction sizes the paper
+        let (paper, _) = parse_input("3,0\n\nfold along x=1\nfold along x=9");
+        assert_eq!((paper.width, paper.height), (4, 1));
+    }
+
+    #[test]
+    fn invalid_folds() {
+        // larger bottom part moves the top dot down
+        let (mut paper, _) = parse_input("0,0\n2,3");
+        assert_eq!(
+            paper.fold(&[Fold::Row(1), Fold::Column(2)]),
+            Err(FoldError::DotOnFoldLine {
+                fold: Fold::Column(2),
+                dot: (2, 0),
+            })
+        );
+        // the valid fold before stays applied
+        assert_eq!((paper.width, paper.height), (3, 2));
+        assert_eq!(
+            paper.fold(&[Fold::Row(2)]),
+            Err(FoldError::BeyondPaper {
+                fold: Fold::Row(2),
+                size: 2,
+            })
+        );
+        assert_eq!(dots(&paper), [(0, 1), (2, 0)]);
+    }
+}
@@ -2,15 +2,142 @@ mod utils;
 const DAY_ID: utils::DayIdType = 18;
 
 type Num = u8;
+type Depth = u8;
 type Res = u32;
 
+/// Snailfish number stored as flat in-order list of regular numbers with their nesting depth.
+/// Pair structure is implicit - two neighbors with the same depth form a pair of regular numbers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FlatNum {
+    items: Vec<(Num, Depth)>,
+}
+
+fn parse_input(data: &str) -> Vec<FlatNum> {
+    data.lines().map(|line| line.into()).collect()
+}
+
+impl From<&str> for FlatNum {
+    fn from(s: &str) -> Self {
+        let mut items = Vec::with_capacity(s.len() / 2);
+        let mut depth = 0;
+        for c in s.chars() {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                ',' => {}
+                // all input numbers appear to be single digit only!
+                _ => items.push((c as u8 - b'0', depth)),
+            }
+        }
+        Self { items }
+    }
+}
+
+impl From<&Node> for FlatNum {
+    fn from(node: &Node) -> Self {
+        fn flatten(node: &Node, depth: Depth, items: &mut Vec<(Num, Depth)>) {
+            match node {
+                Node::Number(n) => items.push((*n, depth)),
+                Node::Pair(left, right) => {
+                    flatten(left, depth + 1, items);
+                    flatten(right, depth + 1, items);
+                }
+            }
+        }
+        let mut items = vec![];
+        flatten(node, 0, &mut items);
+        Self { items }
+    }
+}
+
+impl FlatNum {
+    pub fn magnitude(&self) -> Res {
+        // collapse neighboring numbers of the same depth as long as possible, stack-wise
+        let mut stack: Vec<(Res, Depth)> = Vec::with_capacity(self.items.len());
+        for &(n, depth) in self.items.iter() {
+            let mut current = (n as Res, depth);
+            while let Some(&(left, left_depth)) = stack.last() {
+                if left_depth != current.1 {
+                    break;
+                }
+                stack.pop();
+                current = (3 * left + 2 * current.0, current.1 - 1);
+            }
+            stack.push(current);
+        }
+        stack[0].0
+    }
+
+    #[allow(dead_code)]
+    pub fn debug_string(&self) -> String {
+        Node::from(self).debug_string()
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        let mut items = Vec::with_capacity(self.items.len() + other.items.len());
+        items.extend(self.items.iter().map(|&(n, d)| (n, d + 1)));
+        items.extend(other.items.iter().map(|&(n, d)| (n, d + 1)));
+        Self { items }
+    }
+
+    /// Explodes all pairs nested inside four pairs, from left to right.
+    fn explode_all(&mut self) {
+        let mut i = 0;
+        while i < self.items.len() {
+            let (left, depth) = self.items[i];
+            if depth <= 4 {
+                i += 1;
+                continue;
+            }
+            // after addition of reduced numbers, the too deep numbers always come as pair
+            let (right, _) = self.items[i + 1];
+            if i > 0 {
+                self.items[i - 1].0 += left;
+            }
+            if let Some(next) = self.items.get_mut(i + 2) {
+                next.0 += right;
+            }
+            self.items[i] = (0, depth - 1);
+            self.items.remove(i + 1);
+        }
+    }
+
+    fn split(&mut self) -> bool {
+        match self.items.iter().position(|&(n, _)| n >= 10) {
+            Some(i) => {
+                let (n, depth) = self.items[i];
+                self.items[i] = (n / 2, depth + 1);
+                self.items.insert(i + 1, (n.div_ceil(2), depth + 1));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn reduce(&mut self) {
+        // explosions never produce new too deep pairs, so a single sweep handles all of them
+        loop {
+            self.explode_all();
+            if !self.split() {
+                break;
+            }
+        }
+    }
+}
+
+fn sum_and_reduce_flat(left: &FlatNum, right: &FlatNum) -> FlatNum {
+    let mut sum = left.add(right);
+    sum.reduce();
+    sum
+}
+
 #[derive(Debug, Clone)]
 enum Node {
     Number(Num),
     Pair(Box<Node>, Box<Node>),
 }
 
-fn parse_input(data: &str) -> Vec<Node> {
+fn parse_input_tree(data: &str) -> Vec<Node> {
     data.lines().map(|line| line.into()).collect()
 }
 
@@ -37,6 +164,25 @@ impl From<&str> for Node {
     }
 }
 
+impl From<&FlatNum> for Node {
+    fn from(num: &FlatNum) -> Self {
+        // same stack-wise collapsing as in magnitude computation, just building the pairs
+        let mut stack: Vec<(Node, Depth)> = Vec::with_capacity(num.items.len());
+        for &(n, depth) in num.items.iter() {
+            let mut current = (Node::Number(n), depth);
+            while matches!(stack.last(), Some((_, d)) if *d == current.1) {
+                let (left, _) = stack.pop().unwrap();
+                current = (
+                    Node::Pair(Box::new(left), Box::new(current.0)),
+                    current.1 - 1,
+                );
+            }
+            stack.push(current);
+        }
+        stack.pop().unwrap().0
+    }
+}
+
 impl Node {
     pub fn val(&self) -> Num {
         match self {
@@ -100,7 +246,7 @@ impl Node {
     }
 }
 
-fn sum_and_reduce(left: &Node, right: &Node) -> Node {
+fn sum_and_reduce_tree(left: &Node, right: &Node) -> Node {
     let mut sum = Node::Pair(Box::new(left.clone()), Box::new(right.clone()));
     sum.reduce();
     sum
@@ -140,15 +286,57 @@ fn explode_node<'a>(
     false
 }
 
-fn solve_part1(nodes: &[Node]) -> Res {
+fn solve_part1(nums: &[FlatNum]) -> Res {
+    let mut sum = nums[0].clone();
+    for num in nums.iter().skip(1) {
+        sum = sum_and_reduce_flat(&sum, num);
+    }
+    sum.magnitude()
+}
+
+fn solve_part2(nums: &[FlatNum]) -> Res {
+    let len = nums.len();
+    let workers = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(len.max(1));
+
+    // every worker takes care of every n-th left operand, paired with all the right ones
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|worker| {
+                scope.spawn(move || {
+                    let mut max_magnitude = 0;
+                    for i in (worker..len).step_by(workers) {
+                        for j in 0..len {
+                            if i == j {
+                                continue;
+                            }
+                            let magnitude = sum_and_reduce_flat(&nums[i], &nums[j]).magnitude();
+                            max_magnitude = magnitude.max(max_magnitude);
+                        }
+                    }
+                    max_magnitude
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .max()
+            .unwrap_or(0)
+    })
+}
+
+fn solve_part1_tree(nodes: &[Node]) -> Res {
     let mut sum_node = nodes[0].clone();
     for node in nodes.iter().skip(1) {
-        sum_node = sum_and_reduce(&sum_node, node);
+        sum_node = sum_and_reduce_tree(&sum_node, node);
     }
     sum_node.magnitude()
 }
 
-fn solve_part2(nodes: &[Node]) -> Res {
+fn solve_part2_tree(nodes: &[Node]) -> Res {
     let mut max_magnitude = 0;
 
     let len = nodes.len();
@@ -158,7 +346,7 @@ fn solve_part2(nodes: &[Node]) -> Res {
                 continue;
             }
             let mut sum_node = nodes[i].clone();
-            sum_node = sum_and_reduce(&sum_node, &nodes[j]);
+            sum_node = sum_and_reduce_tree(&sum_node, &nodes[j]);
             max_magnitude = sum_node.magnitude().max(max_magnitude);
         }
     }
@@ -166,6 +354,72 @@ fn solve_part2(nodes: &[Node]) -> Res {
     max_magnitude
 }
 
-generate_main!();
+fn main() {
+    let data_str = utils::string_from_input(DAY_ID);
+    let data;
+    print_timed_result!("parse input", data = parse_input(&data_str));
+    print_timed_result!("part 1", solve_part1(&data));
+    print_timed_result!("part 2", solve_part2(&data));
+
+    // original boxed tree implementation, kept as benchmark baseline
+    let tree_data;
+    print_timed_result!(
+        "parse input (tree)",
+        tree_data = parse_input_tree(&data_str)
+    );
+    print_timed_result!("part 1 (tree)", solve_part1_tree(&tree_data));
+    print_timed_result!("part 2 (tree)", solve_part2_tree(&tree_data));
+}
 
 generate_tests!(4140, 3993);
+
+#[cfg(test)]
+mod flat_tests {
+    use super::*;
+
+    #[test]
+    fn flat_matches_tree() {
+        let data_str = utils::string_from_sample(DAY_ID);
+        let nums = parse_input(&data_str);
+        let nodes = parse_input_tree(&data_str);
+        for (num, node) in nums.iter().zip(nodes.iter()) {
+            assert_eq!(num, &FlatNum::from(node));
+            assert_eq!(num.debug_string(), node.debug_string());
+        }
+        assert_eq!(solve_part1(&nums), solve_part1_tree(&nodes));
+        assert_eq!(solve_part2(&nums), solve_part2_tree(&nodes));
+    }
+
+    #[test]
+    fn reduce_steps() {
+        for (left, right, result) in [
+            (
+                "[[[[4,3],4],4],[7,[[8,4],9]]]",
+                "[1,1]",
+                "[[[[0,7],4],[[7,8],[6,0]]],[8,1]]",
+            ),
+            (
+                "[[[0,[4,5]],[0,0]],[[[4,5],[2,6]],[9,5]]]",
+                "[7,[[[3,7],[4,3]],[[6,3],[8,8]]]]",
+                "[[[[4,0],[5,4]],[[7,7],[6,0]]],[[8,[7,7]],[[7,9],[5,0]]]]",
+            ),
+        ] {
+            let sum = sum_and_reduce_flat(&left.into(), &right.into());
+            assert_eq!(sum.debug_string(), result);
+        }
+    }
+
+    #[test]
+    fn magnitudes() {
+        for (num, result) in [
+            ("[[1,2],[[3,4],5]]", 143),
+            ("[[[[0,7],4],[[7,8],[6,0]]],[8,1]]", 1384),
+            (
+                "[[[[8,7],[7,7]],[[8,6],[7,7]]],[[[0,7],[6,6]],[8,7]]]",
+                3488,
+            ),
+        ] {
+            assert_eq!(FlatNum::from(num).magnitude(), result);
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{create_dir_all, write};
+use std::path::Path;
 
 mod utils;
 const DAY_ID: utils::DayIdType = 19;
@@ -55,18 +58,15 @@ fn parse_input(data: &str) -> Vec<Scanner> {
 }
 
 impl Beacon {
-    pub fn abs(&self) -> Beacon {
-        Beacon {
-            x: self.x.abs(),
-            y: self.y.abs(),
-            z: self.z.abs(),
-        }
-    }
-
     pub fn size(&self) -> usize {
         (self.x.pow(2) + self.y.pow(2) + self.z.pow(2)) as usize
     }
 
+    pub fn manhattan(&self, other: &Beacon) -> Res {
+        let d = self.distance(other);
+        (d.x.abs() + d.y.abs() + d.z.abs()) as Res
+    }
+
     pub fn distance(&self, other: &Beacon) -> Beacon {
         Beacon {
             x: self.x - other.x,
@@ -75,69 +75,144 @@ impl Beacon {
         }
     }
 
-    pub fn rotate(&self, index: usize) -> Beacon {
-        let &Self { x, y, z } = self;
-        let transformed = match index {
-            0 => (-z, -y, -x),
-            1 => (-z, -x, y),
-            2 => (-z, x, -y),
-            3 => (-z, y, x),
-            4 => (-y, -z, x),
-            5 => (-y, -x, -z),
-            6 => (-y, x, z),
-            7 => (-y, z, -x),
-            8 => (-x, -z, -y),
-            9 => (-x, -y, z),
-            10 => (-x, y, -z),
-            11 => (-x, z, y),
-            12 => (x, -z, y),
-            13 => (x, -y, -z),
-            14 => (x, y, z),
-            15 => (x, z, -y),
-            16 => (y, -z, -x),
-            17 => (y, -x, z),
-            18 => (y, x, -z),
-            19 => (y, z, x),
-            20 => (z, -y, x),
-            21 => (z, -x, -y),
-            22 => (z, x, y),
-            23 => (z, y, -x),
-            _ => unreachable!(),
-        };
+    pub fn shift(&self, offset: &Beacon) -> Beacon {
         Beacon {
-            x: transformed.0,
-            y: transformed.1,
-            z: transformed.2,
+            x: self.x + offset.x,
+            y: self.y + offset.y,
+            z: self.z + offset.z,
+        }
+    }
+}
+
+/// Proper rotation of 3D space by multiples of 90 degrees, as integer 3x3 matrix.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct Rotation {
+    m: [[Num; 3]; 3],
+}
+
+impl Rotation {
+    pub fn identity() -> Self {
+        Self {
+            m: [[1, 0, 0], [0, 1, 0], [0, 0, 1]],
+        }
+    }
+
+    /// Quarter turn around x axis.
+    pub fn quarter_x() -> Self {
+        Self {
+            m: [[1, 0, 0], [0, 0, -1], [0, 1, 0]],
+        }
+    }
+
+    /// Quarter turn around z axis.
+    pub fn quarter_z() -> Self {
+        Self {
+            m: [[0, -1, 0], [1, 0, 0], [0, 0, 1]],
+        }
+    }
+
+    /// All the 24 orientations, generated as closure of quarter turns around x and z axes.
+    pub fn all() -> Vec<Rotation> {
+        let generators = [Self::quarter_x(), Self::quarter_z()];
+        let mut group = vec![Self::identity()];
+        let mut i = 0;
+        while i < group.len() {
+            for g in generators.iter() {
+                let r = g.compose(&group[i]);
+                if !group.contains(&r) {
+                    group.push(r);
+                }
+            }
+            i += 1;
+        }
+        group
+    }
+
+    /// Rotation performing `other` first and `self` afterwards.
+    pub fn compose(&self, other: &Rotation) -> Rotation {
+        let mut m = [[0; 3]; 3];
+        for (row, m_row) in m.iter_mut().enumerate() {
+            for (col, cell) in m_row.iter_mut().enumerate() {
+                *cell = (0..3).map(|k| self.m[row][k] * other.m[k][col]).sum();
+            }
+        }
+        Rotation { m }
+    }
+
+    /// Inverse of orthogonal matrix is just its transposition.
+    #[allow(dead_code)]
+    pub fn inverse(&self) -> Rotation {
+        let mut m = [[0; 3]; 3];
+        for (row, m_row) in m.iter_mut().enumerate() {
+            for (col, cell) in m_row.iter_mut().enumerate() {
+                *cell = self.m[col][row];
+            }
+        }
+        Rotation { m }
+    }
+
+    #[allow(dead_code)]
+    pub fn determinant(&self) -> Num {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn apply(&self, b: &Beacon) -> Beacon {
+        let m = &self.m;
+        Beacon {
+            x: m[0][0] * b.x + m[0][1] * b.y + m[0][2] * b.z,
+            y: m[1][0] * b.x + m[1][1] * b.y + m[1][2] * b.z,
+            z: m[2][0] * b.x + m[2][1] * b.y + m[2][2] * b.z,
         }
     }
 }
 
 impl Scanner {
-    pub fn rotate(&self, var: usize) -> Scanner {
-        let id = 100 * self.id + var;
-        let beacons = self.beacons.iter().map(|b| b.rotate(var)).collect();
-        Scanner { id, beacons }
-    }
-
-    pub fn extend_by(&mut self, other: &Scanner, offset: &Beacon) {
-        let Beacon { x, y, z } = offset;
-        let beacons = &mut self.beacons;
-        for b in other.beacons.iter() {
-            let fixed = Beacon {
-                x: b.x + x,
-                y: b.y + y,
-                z: b.z + z,
-            };
-            if !beacons.contains(&fixed) {
-                beacons.push(fixed);
+    pub fn rotate(&self, rotation: &Rotation) -> Scanner {
+        let beacons = self.beacons.iter().map(|b| rotation.apply(b)).collect();
+        Scanner {
+            id: self.id,
+            beacons,
+        }
+    }
+
+    /// Sorted squared distances between all pairs of scanner's beacons.
+    /// These do not depend on scanner orientation, so overlapping scanners share lots of them.
+    pub fn fingerprint(&self) -> Vec<Num> {
+        let mut distances = vec![];
+        for (i, b1) in self.beacons.iter().enumerate() {
+            for b2 in self.beacons.iter().skip(i + 1) {
+                distances.push(b1.distance(b2).size() as Num);
             }
         }
-        beacons.sort_unstable();
-        beacons.dedup();
+        distances.sort_unstable();
+        distances
     }
 }
 
-fn scanners_overlap(left: &Scanner, right: &Scanner) -> Option<Beacon> {
+/// Number of common items of two sorted fingerprints, counting duplicates.
+fn fingerprints_overlap(left: &[Num], right: &[Num]) -> usize {
+    let (mut i, mut j, mut count) = (0, 0, 0);
+    while i < left.len() && j < right.len() {
+        match left[i].cmp(&right[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                count += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    count
+}
+
+/// Minimal number of common beacons to consider two scanners overlapping, as per puzzle text.
+const OVERLAP_THRESHOLD: usize = 12;
+
+fn scanners_overlap(left: &Scanner, right: &Scanner, threshold: usize) -> Option<Beacon> {
     let mut distances: HashMap<Beacon, HashSet<Beacon>> = HashMap::new();
     for lb in &left.beacons {
         for rb in &right.beacons {
@@ -147,78 +222,218 @@ fn scanners_overlap(left: &Scanner, right: &Scanner) -> Option<Beacon> {
     }
     distances
         .iter()
-        .find(|(_, e)| e.len() >= 12)
+        .find(|(_, e)| e.len() >= threshold)
         .map(|entry| entry.0)
         .copied()
 }
 
-fn solve_part1(scanners: &[Scanner]) -> Res {
-    let mut acc = scanners[0].clone();
-    let mut scanners = scanners.iter().skip(1).cloned().collect::<Vec<Scanner>>();
-
-    while !scanners.is_empty() {
-        'attempt: for i in 0..scanners.len() {
-            for var in 0..24 {
-                let rotated = scanners[i].rotate(var);
-                if let Some(offset) = scanners_overlap(&acc, &rotated) {
-                    acc.extend_by(&rotated, &offset);
-                    scanners.remove(i);
-                    break 'attempt;
-                }
-            }
+/// Placement of single scanner within the coordinate system of another one (typically scanner 0).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct ScannerPlacement {
+    position: Beacon,
+    /// rotation turning scanner's own readings into the reference orientation
+    rotation: Rotation,
+}
+
+impl ScannerPlacement {
+    pub fn origin() -> Self {
+        Self {
+            position: Beacon { x: 0, y: 0, z: 0 },
+            rotation: Rotation::identity(),
         }
     }
 
-    acc.beacons.len()
-}
+    /// Converts beacon position relative to the placed scanner into reference coordinates.
+    pub fn transform(&self, b: &Beacon) -> Beacon {
+        self.rotation.apply(b).shift(&self.position)
+    }
 
-fn solve_part2(scanners: &[Scanner]) -> Res {
-    let mut acc = scanners[0].clone();
-    let mut scanners = scanners.iter().skip(1).cloned().collect::<Vec<Scanner>>();
-
-    let mut scanner_distances: Vec<Beacon> = vec![];
-
-    while !scanners.is_empty() {
-        'attempt: for i in 0..scanners.len() {
-            for var in 0..24 {
-                let rotated = scanners[i].rotate(var);
-                if let Some(offset) = scanners_overlap(&acc, &rotated) {
-                    acc.extend_by(&rotated, &offset);
-                    scanner_distances.push(offset);
-                    scanners.remove(i);
-                    break 'attempt;
-                }
-            }
+    /// Chains placements - `relative` placement within this scanner becomes placement
+    /// within the reference system of this scanner.
+    pub fn then(&self, relative: &ScannerPlacement) -> ScannerPlacement {
+        ScannerPlacement {
+            position: self.transform(&relative.position),
+            rotation: self.rotation.compose(&relative.rotation),
         }
     }
+}
+
+/// Result of aligning all the scanners into the coordinate system of scanner 0.
+#[derive(Debug, Clone)]
+struct Alignment {
+    placements: HashMap<usize, ScannerPlacement>,
+    beacons: Vec<Beacon>,
+}
+
+/// Finds placement of `right` scanner within coordinates of `left` scanner, if they overlap.
+fn align_pair(
+    left: &Scanner,
+    right: &Scanner,
+    rotations: &[Rotation],
+    threshold: usize,
+) -> Option<ScannerPlacement> {
+    rotations.iter().find_map(|rotation| {
+        let rotated = right.rotate(rotation);
+        scanners_overlap(left, &rotated, threshold).map(|position| ScannerPlacement {
+            position,
+            rotation: *rotation,
+        })
+    })
+}
+
+/// Aligns scanners one by one in breadth-first manner starting from scanner 0.
+/// Each scanner is matched against individual already aligned scanners only,
+/// and only if their fingerprints share enough distances to possibly overlap.
+/// Relative placements are composed along the path back to scanner 0.
+/// Scanners not reachable from scanner 0 are left out of the placements.
+fn align_scanners(scanners: &[Scanner], threshold: usize) -> Alignment {
+    let rotations = Rotation::all();
+    let fingerprints: Vec<Vec<Num>> = scanners.iter().map(|s| s.fingerprint()).collect();
+    let min_common_distances = threshold * threshold.saturating_sub(1) / 2;
 
-    let mut taxis: Vec<Vec<Beacon>> = vec![];
+    let mut aligned: Vec<Option<ScannerPlacement>> = vec![None; scanners.len()];
+    aligned[0] = Some(ScannerPlacement::origin());
 
-    for (i, b1) in scanner_distances.iter().enumerate() {
-        let mut dists = vec![];
-        for (j, b2) in scanner_distances.iter().enumerate() {
-            if i == j {
+    let mut queue = VecDeque::from([0]);
+    while let Some(from) = queue.pop_front() {
+        let from_placement = aligned[from].unwrap();
+        for to in 0..scanners.len() {
+            if aligned[to].is_some()
+                || fingerprints_overlap(&fingerprints[from], &fingerprints[to])
+                    < min_common_distances
+            {
                 continue;
             }
-            dists.push(b1.distance(b2).abs());
+            if let Some(relative) =
+                align_pair(&scanners[from], &scanners[to], &rotations, threshold)
+            {
+                aligned[to] = Some(from_placement.then(&relative));
+                queue.push_back(to);
+            }
         }
-        dists.sort_unstable();
-        taxis.push(dists);
     }
-    taxis.sort_unstable();
 
-    let mut max_dist = 0;
-    let mut max_beacon = taxis[0][0];
+    let placements: HashMap<usize, ScannerPlacement> = scanners
+        .iter()
+        .zip(aligned.iter())
+        .filter_map(|(scanner, placement)| placement.map(|p| (scanner.id, p)))
+        .collect();
+
+    let mut beacons = scanners
+        .iter()
+        .zip(aligned.iter())
+        .filter_map(|(scanner, placement)| placement.map(|p| (scanner, p)))
+        .flat_map(|(scanner, p)| scanner.beacons.iter().map(move |b| p.transform(b)))
+        .collect::<HashSet<Beacon>>()
+        .into_iter()
+        .collect::<Vec<Beacon>>();
+    beacons.sort_unstable();
+
+    Alignment {
+        placements,
+        beacons,
+    }
+}
+
+impl Alignment {
+    /// Scanner positions ordered by scanner id.
+    fn sorted_scanners(&self) -> Vec<(usize, Beacon)> {
+        let mut scanners: Vec<(usize, Beacon)> = self
+            .placements
+            .iter()
+            .map(|(&id, placement)| (id, placement.position))
+            .collect();
+        scanners.sort_unstable();
+        scanners
+    }
+
+    /// One row per point - scanners first (with their id), followed by beacons.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("kind,id,x,y,z\n");
+        for (id, Beacon { x, y, z }) in self.sorted_scanners() {
+            csv += &format!("scanner,{},{},{},{}\n", id, x, y, z);
+        }
+        for Beacon { x, y, z } in self.beacons.iter() {
+            csv += &format!("beacon,,{},{},{}\n", x, y, z);
+        }
+        csv
+    }
+
+    /// ASCII PLY point cloud, scanners colored red and beacons white.
+    pub fn to_ply(&self) -> String {
+        let scanners = self.sorted_scanners();
+        let mut ply = String::from("ply\nformat ascii 1.0\n");
+        ply += &format!("element vertex {}\n", scanners.len() + self.beacons.len());
+        for property in ["float x", "float y", "float z"] {
+            ply += &format!("property {}\n", property);
+        }
+        for property in ["uchar red", "uchar green", "uchar blue"] {
+            ply += &format!("property {}\n", property);
+        }
+        ply += "end_header\n";
+        for (_, Beacon { x, y, z }) in scanners {
+            ply += &format!("{} {} {} 255 0 0\n", x, y, z);
+        }
+        for Beacon { x, y, z } in self.beacons.iter() {
+            ply += &format!("{} {} {} 255 255 255\n", x, y, z);
+        }
+        ply
+    }
+
+    /// Wavefront OBJ vertices with the (widely supported) vertex color extension.
+    pub fn to_obj(&self) -> String {
+        let mut obj = String::new();
+        for (id, Beacon { x, y, z }) in self.sorted_scanners() {
+            obj += &format!("# scanner {}\nv {} {} {} 1 0 0\n", id, x, y, z);
+        }
+        obj += "# beacons\n";
+        for Beacon { x, y, z } in self.beacons.iter() {
+            obj += &format!("v {} {} {} 1 1 1\n", x, y, z);
+        }
+        obj
+    }
+
+    pub fn export(&self, dir: &Path) -> std::io::Result<()> {
+        create_dir_all(dir)?;
+        let prefix = format!("{:02}-map", DAY_ID);
+        write(dir.join(format!("{}.csv", prefix)), self.to_csv())?;
+        write(dir.join(format!("{}.ply", prefix)), self.to_ply())?;
+        write(dir.join(format!("{}.obj", prefix)), self.to_obj())
+    }
+}
+
+fn beacon_count(alignment: &Alignment) -> Res {
+    alignment.beacons.len()
+}
+
+// main shares single alignment for both parts, these align separately for the sample tests
+#[allow(dead_code)]
+fn solve_part1(scanners: &[Scanner]) -> Res {
+    beacon_count(&align_scanners(scanners, OVERLAP_THRESHOLD))
+}
 
-    for b in taxis.iter().flatten() {
-        let dist = b.size();
-        if dist > max_dist {
-            max_dist = dist;
-            max_beacon = *b;
+fn max_manhattan_distance(positions: &[Beacon]) -> Res {
+    let mut max_dist = 0;
+    for (i, p1) in positions.iter().enumerate() {
+        for p2 in positions.iter().skip(i + 1) {
+            max_dist = max_dist.max(p1.manhattan(p2));
         }
     }
+    max_dist
+}
 
-    (max_beacon.x + max_beacon.y + max_beacon.z) as usize
+fn scanners_distance(alignment: &Alignment) -> Res {
+    let positions: Vec<Beacon> = alignment
+        .placements
+        .values()
+        .map(|placement| placement.position)
+        .collect();
+    max_manhattan_distance(&positions)
+}
+
+#[allow(dead_code)]
+fn solve_part2(scanners: &[Scanner]) -> Res {
+    scanners_distance(&align_scanners(scanners, OVERLAP_THRESHOLD))
 }
 
 #[allow(dead_code)]
@@ -231,6 +446,144 @@ fn debug_vec_vec_beacons(taxis: &[Vec<Beacon>]) {
     }
 }
 
-generate_main!();
+/// Run with `-- --export <dir>` to also write the reconstructed map as CSV, PLY and OBJ files.
+fn main() {
+    let data_str = utils::string_from_input(DAY_ID);
+    let data;
+    print_timed_result!("parse input", data = parse_input(&data_str));
+    // both parts share single alignment, which is the expensive bit
+    let alignment;
+    print_timed_result!(
+        "align scanners",
+        alignment = align_scanners(&data, OVERLAP_THRESHOLD)
+    );
+    print_timed_result!("part 1", beacon_count(&alignment));
+    print_timed_result!("part 2", scanners_distance(&alignment));
+
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(pos) = args.iter().position(|arg| arg == "--export") {
+        let dir = args.get(pos + 1).map(String::as_str).unwrap_or(".");
+        alignment.export(Path::new(dir)).unwrap();
+        println!("map exported to {}", dir);
+    }
+}
 
 generate_tests!(79, 3621);
+
+#[cfg(test)]
+mod alignment_tests {
+    use super::*;
+
+    #[test]
+    fn sample_placements() {
+        let data_str = utils::string_from_sample(DAY_ID);
+        let scanners = parse_input(&data_str);
+        let alignment = align_scanners(&scanners, OVERLAP_THRESHOLD);
+        assert_eq!(alignment.placements.len(), scanners.len());
+        for (id, x, y, z) in [
+            (0, 0, 0, 0),
+            (1, 68, -1246, -43),
+            (2, 1105, -1205, 1229),
+            (3, -92, -2380, -20),
+            (4, -20, -1133, 1061),
+        ] {
+            assert_eq!(alignment.placements[&id].position, Beacon { x, y, z });
+        }
+        assert_eq!(alignment.placements[&0].rotation, Rotation::identity());
+    }
+
+    #[test]
+    fn fingerprints() {
+        assert_eq!(fingerprints_overlap(&[1, 2, 2, 3, 5], &[2, 2, 2, 5, 7]), 3);
+        assert_eq!(fingerprints_overlap(&[1, 3], &[2, 4]), 0);
+
+        let data_str = utils::string_from_sample(DAY_ID);
+        let scanners = parse_input(&data_str);
+        let rotated = scanners[1].rotate(&Rotation::all()[5]);
+        assert_eq!(scanners[1].fingerprint(), rotated.fingerprint());
+    }
+
+    #[test]
+    fn overlap_threshold() {
+        let data_str = utils::string_from_sample(DAY_ID);
+        let scanners = parse_input(&data_str);
+        let alignment = align_scanners(&scanners, 30);
+        assert_eq!(alignment.placements.len(), 1);
+        assert_eq!(alignment.beacons.len(), scanners[0].beacons.len());
+    }
+
+    #[test]
+    fn manhattan_not_euclidean() {
+        let positions = [
+            Beacon { x: 0, y: 0, z: 0 },
+            Beacon {
+                x: 10,
+                y: 10,
+                z: 10,
+            },
+            Beacon { x: 0, y: 0, z: 25 },
+        ];
+        assert_eq!(positions[0].manhattan(&positions[1]), 30);
+        // euclidean farthest pair is 0-2 with manhattan distance of just 25
+        assert_eq!(max_manhattan_distance(&positions), 35);
+        // scanner 0 has to be taken into account as well
+        assert_eq!(max_manhattan_distance(&positions[..2]), 30);
+    }
+
+    #[test]
+    fn rotation_group() {
+        let all = Rotation::all();
+        assert_eq!(all.len(), 24);
+        assert_eq!(all.iter().collect::<HashSet<_>>().len(), 24);
+        for r in all.iter() {
+            assert_eq!(r.determinant(), 1);
+            assert_eq!(r.compose(&r.inverse()), Rotation::identity());
+            assert!(all.contains(&r.inverse()));
+            for other in all.iter() {
+                assert!(all.contains(&r.compose(other)));
+            }
+        }
+    }
+
+    #[test]
+    fn rotation_apply() {
+        let b = Beacon { x: 1, y: 2, z: 3 };
+        let qx = Rotation::quarter_x();
+        assert_eq!(qx.apply(&b), Beacon { x: 1, y: -3, z: 2 });
+        assert_eq!(qx.inverse().apply(&qx.apply(&b)), b);
+        let qz = Rotation::quarter_z();
+        assert_eq!(qz.compose(&qx).apply(&b), qz.apply(&qx.apply(&b)));
+    }
+
+    #[test]
+    fn exports() {
+        let alignment = Alignment {
+            placements: HashMap::from([
+                (
+                    1,
+                    ScannerPlacement {
+                        position: Beacon { x: 5, y: 6, z: 7 },
+                        rotation: Rotation::identity(),
+                    },
+                ),
+                (0, ScannerPlacement::origin()),
+            ]),
+            beacons: vec![Beacon { x: -1, y: 2, z: 3 }],
+        };
+        assert_eq!(
+            alignment.to_csv(),
+            "kind,id,x,y,z\nscanner,0,0,0,0\nscanner,1,5,6,7\nbeacon,,-1,2,3\n"
+        );
+        let ply = alignment.to_ply();
+        assert!(ply.starts_with("ply\nformat ascii 1.0\nelement vertex 3\n"));
+        assert!(ply.ends_with("end_header\n0 0 0 255 0 0\n5 6 7 255 0 0\n-1 2 3 255 255 255\n"));
+        assert_eq!(
+            alignment
+                .to_obj()
+                .lines()
+                .filter(|l| l.starts_with("v "))
+                .count(),
+            3
+        );
+    }
+}
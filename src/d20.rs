@@ -1,146 +1,492 @@
-use std::collections::HashSet;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::Path;
 
+mod automaton;
 mod utils;
+use automaton::{Edge, Grid, LocalRule, Neighborhood};
+
 const DAY_ID: utils::DayIdType = 20;
 
 type Res = usize;
 
-type Lights = HashSet<(i32, i32)>;
+/// Number of all possible 3x3 pixel windows.
+const ALGO_LEN: usize = 512;
 
 fn parse_input(data: &str) -> Screen {
-    let mut lines = data.lines();
+    Screen::try_from(data).unwrap()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ImageError {
+    JaggedRow {
+        row: usize,
+        len: usize,
+        expected: usize,
+    },
+    InvalidPixel {
+        row: usize,
+        col: usize,
+        ch: char,
+    },
+}
 
-    let algo = lines.next().unwrap().chars().collect::<Vec<char>>();
+impl TryFrom<&str> for Screen {
+    type Error = ImageError;
 
-    lines.next();
+    fn try_from(data: &str) -> Result<Self, Self::Error> {
+        let mut lines = data.lines();
 
-    let mut lights = HashSet::new();
-    let mut max_row = 0;
-    let mut max_col = 0;
-    for (row, line) in lines.enumerate() {
-        max_row += 1;
-        max_col = line.len() as i32;
-        for (col, ch) in line.chars().enumerate() {
-            if ch == '#' {
-                lights.insert((row as i32, col as i32));
+        let algo = lines.next().unwrap().chars().map(|ch| ch == '#').collect();
+
+        lines.next();
+
+        let lines: Vec<&str> = lines.collect();
+        let cols = lines.first().map_or(0, |line| line.len());
+        let mut image = BitImage::new(lines.len(), cols);
+        for (row, line) in lines.iter().enumerate() {
+            if line.len() != cols {
+                return Err(ImageError::JaggedRow {
+                    row,
+                    len: line.len(),
+                    expected: cols,
+                });
+            }
+            for (col, ch) in line.chars().enumerate() {
+                match ch {
+                    '#' => image.set(row, col),
+                    '.' => {}
+                    _ => return Err(ImageError::InvalidPixel { row, col, ch }),
+                }
             }
         }
+
+        Ok(Screen::new(algo, image))
     }
+}
 
-    let is_charging = algo[0] == '#';
-    let is_shutting = algo[algo.len() - 1] == '.';
+/// Dense row-major bitset image, each row starting at new word.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BitImage {
+    rows: usize,
+    cols: usize,
+    stride: usize,
+    words: Vec<u64>,
+}
+
+impl BitImage {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        let stride = cols.div_ceil(64);
+        Self {
+            rows,
+            cols,
+            stride,
+            words: vec![0; rows * stride],
+        }
+    }
 
-    Screen {
-        algo,
-        is_charging,
-        is_shutting,
-        lights,
-        min_row: 0,
-        max_row,
-        min_col: 0,
-        max_col,
+    pub fn get(&self, row: usize, col: usize) -> bool {
+        (self.words[row * self.stride + col / 64] >> (col % 64)) & 1 == 1
+    }
+
+    pub fn set(&mut self, row: usize, col: usize) {
+        self.words[row * self.stride + col / 64] |= 1 << (col % 64);
+    }
+
+    /// Pixel at possibly out of image position, anything outside having `outer` value.
+    fn get_or(&self, row: isize, col: isize, outer: bool) -> bool {
+        if row < 0 || col < 0 || row >= self.rows as isize || col >= self.cols as isize {
+            outer
+        } else {
+            self.get(row as usize, col as usize)
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
     }
 }
 
+/// Parses plain (ASCII) portable bitmap `P1` or graymap `P2` image.
+/// Black bitmap pixels and graymap pixels brighter than half of max value are lit.
+/// Bitmap pixels are single digits, which need not be separated by whitespace.
+fn parse_pnm(data: &str) -> BitImage {
+    // comments run till the end of line, the rest are whitespace separated tokens
+    let mut tokens = data
+        .lines()
+        .flat_map(|line| line.split('#').next().unwrap().split_whitespace());
+    let magic = tokens.next().unwrap();
+    let cols = tokens.next().unwrap().parse::<usize>().unwrap();
+    let rows = tokens.next().unwrap().parse::<usize>().unwrap();
+    let mut pixels: Box<dyn Iterator<Item = bool>> = match magic {
+        "P1" => Box::new(tokens.flat_map(str::chars).map(|ch| match ch {
+            '0' => false,
+            '1' => true,
+            _ => panic!("invalid bitmap pixel {}", ch),
+        })),
+        "P2" => {
+            let max_value = tokens.next().unwrap().parse::<u32>().unwrap();
+            Box::new(tokens.map(move |v| 2 * v.parse::<u32>().unwrap() > max_value))
+        }
+        _ => panic!("unsupported image format {}", magic),
+    };
+
+    let mut image = BitImage::new(rows, cols);
+    for row in 0..rows {
+        for col in 0..cols {
+            if pixels.next().unwrap() {
+                image.set(row, col);
+            }
+        }
+    }
+    image
+}
+
 #[derive(Debug, Clone)]
 struct Screen {
-    algo: Vec<char>,
-    is_charging: bool,
-    is_shutting: bool,
-    lights: Lights,
-    min_row: i32,
-    max_row: i32,
-    min_col: i32,
-    max_col: i32,
+    algo: Vec<bool>,
+    /// state of all the infinitely many pixels outside of the image
+    is_outer_lighted: bool,
+    image: BitImage,
 }
 
 impl Screen {
-    fn pixel_value(&self, row: i32, col: i32, is_outer_lighted: bool) -> usize {
-        let mut value = 0;
-        let mut pow = 0;
-        for r in [row + 1, row, row - 1] {
-            for c in [col + 1, col, col - 1] {
-                let is_out = r <= self.min_row
-                    || r >= self.max_row
-                    || c <= self.min_col
-                    || c >= self.max_col;
-                let next_char: usize = if is_out {
-                    is_outer_lighted as usize
-                } else {
-                    self.lights.contains(&(r, c)) as usize
-                };
-                value += next_char * 2usize.pow(pow);
-                pow += 1;
-            }
+    pub fn new(algo: Vec<bool>, image: BitImage) -> Self {
+        assert_eq!(
+            algo.len(),
+            ALGO_LEN,
+            "enhancement algorithm must have exactly {} entries",
+            ALGO_LEN
+        );
+
+        Self {
+            algo,
+            is_outer_lighted: false,
+            image,
         }
-        value
     }
 
-    fn new_pixel(&self, row: i32, col: i32, is_outer_lighted: bool) -> char {
-        let val = self.pixel_value(row, col, is_outer_lighted);
-        self.algo[val]
+    pub fn from_pnm(algo: Vec<bool>, image: &str) -> Self {
+        Self::new(algo, parse_pnm(image))
     }
 
-    pub fn enhance(&mut self, steps: usize) {
-        let mut is_outer_lighted = false;
+    pub fn lit_count(&self) -> usize {
+        self.image.count()
+    }
 
-        for _ in 0..steps {
-            let mut new_lights = HashSet::new();
-
-            self.min_row -= 1;
-            self.max_row += 1;
-            self.min_col -= 1;
-            self.max_col += 1;
-
-            for r in self.min_row..=self.max_row {
-                for c in self.min_col..=self.max_col {
-                    let p = self.new_pixel(r, c, is_outer_lighted);
-                    if p == '#' {
-                        new_lights.insert((r, c));
-                    }
+    /// Column of three vertically adjacent pixels centered at given position, as 3 bits
+    /// spaced by 3 so that they end up in proper place of the 3x3 window index.
+    fn column_bits(&self, row: isize, col: isize) -> usize {
+        let outer = self.is_outer_lighted;
+        let top = self.image.get_or(row - 1, col, outer) as usize;
+        let mid = self.image.get_or(row, col, outer) as usize;
+        let bottom = self.image.get_or(row + 1, col, outer) as usize;
+        top << 6 | mid << 3 | bottom
+    }
+
+    pub fn step(&mut self) {
+        let mut new_image = BitImage::new(self.image.rows + 2, self.image.cols + 2);
+
+        for new_row in 0..new_image.rows {
+            // new image is one pixel larger at each side
+            let row = new_row as isize - 1;
+            // sliding 3x3 window - shift out the leftmost column, bring in next one on the right
+            let mut window = 0;
+            for col in -2..0 {
+                window = (window << 1) & 0b110_110_110 | self.column_bits(row, col);
+            }
+            for new_col in 0..new_image.cols {
+                let col = new_col as isize;
+                window = (window << 1) & 0b110_110_110 | self.column_bits(row, col);
+                if self.algo[window] {
+                    new_image.set(new_row, new_col);
                 }
             }
+        }
 
-            if is_outer_lighted && self.is_shutting {
-                is_outer_lighted = false;
-            } else if self.is_charging {
-                is_outer_lighted = true;
-            }
+        // every outer pixel is surrounded by outer pixels only - all 9 bits off, or all 9 on
+        self.is_outer_lighted = if self.is_outer_lighted {
+            self.algo[ALGO_LEN - 1]
+        } else {
+            self.algo[0]
+        };
+
+        self.image = new_image;
+    }
 
-            self.lights = new_lights;
+    pub fn enhance(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.step();
         }
         // self.debug_print();
     }
 
+    /// Enhances the image, writing each state (including the initial one) as numbered PGM frame.
+    pub fn export_frames(&mut self, steps: usize, dir: &Path) -> std::io::Result<()> {
+        create_dir_all(dir)?;
+        for step in 0..=steps {
+            if step > 0 {
+                self.step();
+            }
+            let name = format!("{:02}-frame-{:03}.pgm", DAY_ID, step);
+            write(dir.join(name), self.to_pgm())?;
+        }
+        Ok(())
+    }
+
+    fn image_rows(&self) -> Vec<Vec<bool>> {
+        (0..self.image.rows)
+            .map(|r| (0..self.image.cols).map(|c| self.image.get(r, c)).collect())
+            .collect()
+    }
+
+    fn pnm_header(&self, magic: &str) -> String {
+        format!("{}\n{} {}\n", magic, self.image.cols, self.image.rows)
+    }
+
+    /// Plain portable bitmap, lit pixels being black.
+    pub fn to_pbm(&self) -> String {
+        let mut pbm = self.pnm_header("P1");
+        for row in self.image_rows() {
+            let line: Vec<&str> = row.iter().map(|&lit| if lit { "1" } else { "0" }).collect();
+            pbm += &line.join(" ");
+            pbm += "\n";
+        }
+        pbm
+    }
+
+    /// Plain portable graymap, lit pixels being white.
+    pub fn to_pgm(&self) -> String {
+        let mut pgm = self.pnm_header("P2");
+        pgm += "255\n";
+        for row in self.image_rows() {
+            let line: Vec<&str> = row
+                .iter()
+                .map(|&lit| if lit { "255" } else { "0" })
+                .collect();
+            pgm += &line.join(" ");
+            pgm += "\n";
+        }
+        pgm
+    }
+
     #[allow(dead_code)]
     pub fn debug_print(&self) {
         println!("--------------------------------");
-        for r in self.min_row..self.max_row {
-            for c in self.min_col..self.max_col {
-                let ch = if self.lights.contains(&(r, c)) {
-                    '#'
-                } else {
-                    '.'
-                };
-                print!("{}", ch);
-            }
-            println!();
+        for row in self.image_rows() {
+            let line: String = row.iter().map(|&lit| if lit { '#' } else { '.' }).collect();
+            println!("{}", line);
         }
     }
 }
 
+/// Enhancement expressed as generic cellular automaton rule. `Screen` does the very same
+/// on dense bitset, which is way faster, so this one serves as reference implementation.
+#[allow(dead_code)]
+struct EnhanceRule {
+    algo: Vec<bool>,
+}
+
+impl LocalRule for EnhanceRule {
+    type Cell = bool;
+
+    fn neighborhood(&self) -> Neighborhood {
+        // whole 3x3 window in reading order, the first one being the most significant bit
+        let offsets = (-1..=1).flat_map(|r| (-1..=1).map(move |c| (r, c)));
+        Neighborhood::Custom(offsets.collect())
+    }
+
+    fn next(&self, _phase: usize, _cell: &bool, neighbors: &[&bool]) -> bool {
+        let index = neighbors
+            .iter()
+            .fold(0, |index, &&lit| index << 1 | lit as usize);
+        self.algo[index]
+    }
+}
+
+impl Screen {
+    #[allow(dead_code)]
+    fn to_grid(&self) -> Grid<bool> {
+        let rows = self.image_rows();
+        Grid::from_rows(rows, Edge::Infinite, self.is_outer_lighted)
+    }
+}
+
 fn solve_part1(screen: &Screen) -> Res {
     let mut screen = screen.clone();
     screen.enhance(2);
-    screen.lights.len()
+    screen.lit_count()
 }
 
 fn solve_part2(screen: &Screen) -> Res {
     let mut screen = screen.clone();
     screen.enhance(50);
-    screen.lights.len()
+    screen.lit_count()
 }
 
-generate_main!();
+/// Run with `-- --image <file>` to enhance plain PBM or PGM image instead of the puzzle one,
+/// `-- --pbm <file>` to also write the fully enhanced image as PBM,
+/// and `-- --frames <dir>` to also write all the 50 enhancement steps as PGM images.
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let arg_after = |flag: &str| {
+        let pos = args.iter().position(|arg| arg == flag)?;
+        args.get(pos + 1).map(String::as_str)
+    };
+
+    let data_str = utils::string_from_input(DAY_ID);
+    let mut data;
+    print_timed_result!("parse input", data = parse_input(&data_str));
+    if let Some(path) = arg_after("--image") {
+        let image = read_to_string(path).unwrap();
+        data = Screen::from_pnm(data.algo.clone(), &image);
+    }
+    print_timed_result!("part 1", solve_part1(&data));
+    print_timed_result!("part 2", solve_part2(&data));
+
+    if let Some(path) = arg_after("--pbm") {
+        let mut screen = data.clone();
+        screen.enhance(50);
+        write(path, screen.to_pbm()).unwrap();
+        println!("enhanced image written to {}", path);
+    }
+    if args.iter().any(|arg| arg == "--frames") {
+        let dir = arg_after("--frames").unwrap_or(".");
+        data.clone().export_frames(50, Path::new(dir)).unwrap();
+        println!("frames exported to {}", dir);
+    }
+}
 
 generate_tests!(35, 3351);
+
+#[cfg(test)]
+mod image_tests {
+    use super::*;
+    use automaton::Automaton;
+
+    #[test]
+    fn pnm_roundtrip() {
+        let data_str = utils::string_from_sample(DAY_ID);
+        let screen = parse_input(&data_str);
+        let pbm = screen.to_pbm();
+        assert!(pbm.starts_with("P1\n5 5\n1 0 0 1 0\n"));
+
+        for image in [pbm, screen.to_pgm()] {
+            let loaded = Screen::from_pnm(screen.algo.clone(), &image);
+            assert_eq!(loaded.image, screen.image);
+        }
+    }
+
+    #[test]
+    fn pnm_comments_and_threshold() {
+        let image = "P2 # graymap\n# size\n3 1\n10\n0 5 6\n";
+        let mut expected = BitImage::new(1, 3);
+        expected.set(0, 2);
+        assert_eq!(parse_pnm(image), expected);
+
+        // bitmap pixels with and without separating whitespace
+        let mut expected = BitImage::new(2, 4);
+        for (row, col) in [(0, 1), (0, 2), (1, 0), (1, 3)] {
+            expected.set(row, col);
+        }
+        assert_eq!(parse_pnm("P1\n4 2\n0110\n1001\n"), expected);
+        assert_eq!(parse_pnm("P1 4 2 0 1 10 1\n0 01\n"), expected);
+    }
+
+    #[test]
+    fn invalid_images() {
+        let algo = ".".repeat(ALGO_LEN);
+        assert_eq!(
+            Screen::try_from(format!("{}\n\n#.#\n.#\n", algo).as_str()).unwrap_err(),
+            ImageError::JaggedRow {
+                row: 1,
+                len: 2,
+                expected: 3
+            }
+        );
+        // wider row used to overwrite bits of the next one
+        assert_eq!(
+            Screen::try_from(format!("{}\n\n#\n{}\n", algo, "#".repeat(70)).as_str()).unwrap_err(),
+            ImageError::JaggedRow {
+                row: 1,
+                len: 70,
+                expected: 1
+            }
+        );
+        assert_eq!(
+            Screen::try_from(format!("{}\n\n#.\n.o\n", algo).as_str()).unwrap_err(),
+            ImageError::InvalidPixel {
+                row: 1,
+                col: 1,
+                ch: 'o'
+            }
+        );
+    }
+
+    #[test]
+    fn wide_image() {
+        let mut image = BitImage::new(3, 130);
+        for col in [0, 63, 64, 127, 129] {
+            image.set(1, col);
+        }
+        assert_eq!(image.count(), 5);
+        assert!(image.get(1, 64) && !image.get(1, 65) && !image.get(0, 64));
+        assert!(image.get_or(-1, 5, true) && !image.get_or(1, 130, false));
+
+        // a single lit pixel spreads into a growing square with "light if anything around" rule
+        let mut algo = vec![true; 512];
+        algo[0] = false;
+        let mut screen = Screen::new(algo, image);
+        screen.enhance(3);
+        assert_eq!(screen.image.cols, 136);
+        assert!(!screen.image.get(0, 0));
+        assert!(screen.image.get(1, 0));
+    }
+
+    #[test]
+    fn outer_background() {
+        // (first bit, last bit) -> outer state in steps 1 to 4
+        for (first, last, expected) in [
+            (false, false, [false, false, false, false]),
+            (false, true, [false, false, false, false]),
+            (true, false, [true, false, true, false]),
+            (true, true, [true, true, true, true]),
+        ] {
+            let mut algo = vec![false; ALGO_LEN];
+            algo[0] = first;
+            algo[ALGO_LEN - 1] = last;
+            let mut screen = Screen::new(algo, BitImage::new(1, 1));
+            for is_lit in expected {
+                screen.step();
+                assert_eq!(screen.is_outer_lighted, is_lit);
+                // image border is next to outer pixels only, so it follows the outer state
+                assert_eq!(screen.image.get(0, 0), is_lit);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "exactly 512 entries")]
+    fn short_algo() {
+        Screen::new(vec![false; 511], BitImage::new(1, 1));
+    }
+
+    #[test]
+    fn automaton_reference() {
+        let data_str = utils::string_from_sample(DAY_ID);
+        let mut screen = parse_input(&data_str);
+        let rule = EnhanceRule {
+            algo: screen.algo.clone(),
+        };
+        let mut automaton = Automaton::new(rule, screen.to_grid());
+        for _ in 0..10 {
+            screen.step();
+            automaton.step();
+            assert_eq!(automaton.grid, screen.to_grid());
+        }
+        automaton.run(40);
+        assert_eq!(
+            automaton.grid.count(|&lit| lit),
+            solve_part2(&parse_input(&data_str))
+        );
+    }
+}
@@ -1,49 +1,244 @@
-use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 
+mod automaton;
 mod utils;
+use automaton::{Edge, Grid, LocalRule, Neighborhood};
+
 const DAY_ID: utils::DayIdType = 25;
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Cucumber {
+    Empty,
+    East,
+    South,
+}
+
+/// Both herds moving on the toroidal sea floor - east facing herd first, south facing second.
+/// `Population` does the same way faster, so this one serves as reference implementation.
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
+struct HerdRule;
+
+impl LocalRule for HerdRule {
+    type Cell = Cucumber;
+
+    fn neighborhood(&self) -> Neighborhood {
+        Neighborhood::VonNeumann
+    }
+
+    fn phases(&self) -> usize {
+        2
+    }
+
+    fn next(&self, phase: usize, cell: &Cucumber, neighbors: &[&Cucumber]) -> Cucumber {
+        let &[up, left, right, down] = neighbors else {
+            unreachable!()
+        };
+        let (herd, behind, ahead) = match phase {
+            0 => (Cucumber::East, left, right),
+            _ => (Cucumber::South, up, down),
+        };
+        if *cell == Cucumber::Empty && *behind == herd {
+            herd
+        } else if *cell == herd && *ahead == Cucumber::Empty {
+            Cucumber::Empty
+        } else {
+            *cell
+        }
+    }
+}
+
+fn parse_grid(data: &str) -> Grid<Cucumber> {
+    let cells = data
+        .lines()
+        .map(|line| {
+            line.chars()
+                .map(|ch| match ch {
+                    '>' => Cucumber::East,
+                    'v' => Cucumber::South,
+                    _ => Cucumber::Empty,
+                })
+                .collect()
+        })
+        .collect();
+
+    Grid::from_rows(cells, Edge::Toroidal, Cucumber::Empty)
+}
+
+fn parse_input(data: &str) -> Population {
+    Population::from(&parse_grid(data))
+}
+
+/// Bits of single row, bit `i` of word `i / 64` being the column `i`.
+type Row = Vec<u64>;
+
+/// Both herds stored as row bitsets, moves being computed on whole rows at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct Population {
     rows: usize,
     cols: usize,
-    easts: HashSet<(usize, usize)>,
-    souths: HashSet<(usize, usize)>,
+    easts: Vec<Row>,
+    souths: Vec<Row>,
 }
 
-fn parse_input(data: &str) -> Population {
-    let mut easts = HashSet::new();
-    let mut souths = HashSet::new();
-
-    for (r, line) in data.lines().enumerate() {
-        for (c, ch) in line.chars().enumerate() {
-            match ch {
-                '>' => {
-                    easts.insert((r, c));
-                }
-                'v' => {
-                    souths.insert((r, c));
+impl Population {
+    fn empty_row(&self) -> Row {
+        vec![0; self.cols.div_ceil(64)]
+    }
+
+    fn get(row: &Row, col: usize) -> bool {
+        (row[col / 64] >> (col % 64)) & 1 == 1
+    }
+
+    fn set(row: &mut Row, col: usize) {
+        row[col / 64] |= 1 << (col % 64);
+    }
+
+    /// Clears the unused bits beyond the last column.
+    fn masked(&self, mut row: Row) -> Row {
+        if !self.cols.is_multiple_of(64) {
+            *row.last_mut().unwrap() &= (1 << (self.cols % 64)) - 1;
+        }
+        row
+    }
+
+    /// Moves every bit one column to the east, the last column wrapping around to the first one.
+    fn rotate_east(&self, row: &Row) -> Row {
+        let wrapped = Self::get(row, self.cols - 1) as u64;
+        let mut carry = wrapped;
+        let rotated: Row = row
+            .iter()
+            .map(|&w| {
+                let shifted = w << 1 | carry;
+                carry = w >> 63;
+                shifted
+            })
+            .collect();
+        self.masked(rotated)
+    }
+
+    /// Moves every bit one column to the west, the first column wrapping around to the last one.
+    fn rotate_west(&self, row: &Row) -> Row {
+        let mut rotated: Row = (0..row.len())
+            .map(|i| row[i] >> 1 | row.get(i + 1).map_or(0, |next| next << 63))
+            .collect();
+        if Self::get(row, 0) {
+            Self::set(&mut rotated, self.cols - 1);
+        }
+        rotated
+    }
+
+    fn free(&self, row: usize) -> Row {
+        let free = self.easts[row]
+            .iter()
+            .zip(self.souths[row].iter())
+            .map(|(e, s)| !(e | s))
+            .collect();
+        self.masked(free)
+    }
+
+    /// Performs single step of both herds, returns original positions of the cucumbers that moved.
+    pub fn step(&mut self) -> Moves {
+        let mut east_moves = vec![];
+
+        for r in 0..self.rows {
+            // cucumber moves if the cell east of it is free, i.e. free cells seen one column west
+            let free_ahead = self.rotate_west(&self.free(r));
+            let moving: Row = self.easts[r]
+                .iter()
+                .zip(free_ahead.iter())
+                .map(|(e, f)| e & f)
+                .collect();
+            let arrived = self.rotate_east(&moving);
+            for (i, e) in self.easts[r].iter_mut().enumerate() {
+                *e = (*e & !moving[i]) | arrived[i];
+            }
+            east_moves.push(moving);
+        }
+
+        let free: Vec<Row> = (0..self.rows).map(|r| self.free(r)).collect();
+        let moving: Vec<Row> = (0..self.rows)
+            .map(|r| {
+                let below = &free[(r + 1) % self.rows];
+                self.souths[r]
+                    .iter()
+                    .zip(below.iter())
+                    .map(|(s, f)| s & f)
+                    .collect()
+            })
+            .collect();
+        for r in 0..self.rows {
+            let above = &moving[(r + self.rows - 1) % self.rows];
+            for (i, s) in self.souths[r].iter_mut().enumerate() {
+                *s = (*s & !moving[r][i]) | above[i];
+            }
+        }
+
+        Moves {
+            cols: self.cols,
+            easts: east_moves,
+            souths: moving,
+        }
+    }
+
+    /// Reverts the step that made given moves - south facing herd first, east facing second.
+    #[allow(dead_code)]
+    pub fn undo(&mut self, moves: &Moves) {
+        for r in 0..self.rows {
+            let above = &moves.souths[(r + self.rows - 1) % self.rows];
+            for (i, s) in self.souths[r].iter_mut().enumerate() {
+                *s = (*s & !above[i]) | moves.souths[r][i];
+            }
+        }
+        for r in 0..self.rows {
+            let arrived = self.rotate_east(&moves.easts[r]);
+            for (i, e) in self.easts[r].iter_mut().enumerate() {
+                *e = (*e & !arrived[i]) | moves.easts[r][i];
+            }
+        }
+    }
+
+    /// Runs the herds till they stop, returns the number of the first step in which nothing
+    /// moved, or `None` when still moving after `max_steps` steps.
+    pub fn run(&mut self, max_steps: Option<usize>) -> Option<usize> {
+        self.run_with_frames(max_steps, None)
+    }
+
+    /// Same as `run`, with every `every`-th state (the initial one included) written to `frames`.
+    pub fn run_with_frames(
+        &mut self,
+        max_steps: Option<usize>,
+        mut frames: Option<(&mut dyn Write, usize)>,
+    ) -> Option<usize> {
+        let mut steps: usize = 0;
+        while max_steps.is_none_or(|max| steps < max) {
+            if let Some((out, every)) = frames.as_mut() {
+                if steps.is_multiple_of(*every) {
+                    writeln!(out, "After {} steps:\n{}", steps, self.render()).unwrap();
                 }
-                _ => {}
+            }
+            steps += 1;
+            if self.step().is_empty() {
+                return Some(steps);
             }
         }
+        None
     }
-    let rows = data.lines().count();
-    let cols = data.lines().next().unwrap().len();
 
-    Population {
-        rows,
-        cols,
-        easts,
-        souths,
+    /// State of the sea floor after given number of steps.
+    pub fn after(&self, steps: usize) -> Population {
+        let mut pop = self.clone();
+        for _ in 0..steps {
+            pop.step();
+        }
+        pop
     }
-}
 
-impl Population {
     fn char_at(&self, row: usize, col: usize) -> char {
-        if self.easts.contains(&(row, col)) {
+        if Self::get(&self.easts[row], col) {
             '>'
-        } else if self.souths.contains(&(row, col)) {
+        } else if Self::get(&self.souths[row], col) {
             'v'
         } else {
             '.'
@@ -52,64 +247,88 @@ impl Population {
 
     #[allow(dead_code)]
     pub fn debug_print(&self) {
+        print!("{}", self.render());
+    }
+
+    pub fn render(&self) -> String {
+        let mut s = String::with_capacity((self.cols + 1) * self.rows);
         for r in 0..self.rows {
             for c in 0..self.cols {
-                print!("{}", self.char_at(r, c));
+                s.push(self.char_at(r, c));
             }
-            println!();
+            s.push('\n');
         }
+        s
     }
+}
 
-    pub fn free_east(&self, row: usize, col: usize) -> bool {
-        let col = (col + 1) % self.cols;
-        !self.easts.contains(&(row, col)) && !self.souths.contains(&(row, col))
-    }
-
-    pub fn free_south(&self, row: usize, col: usize) -> bool {
-        let row = (row + 1) % self.rows;
-        !self.easts.contains(&(row, col)) && !self.souths.contains(&(row, col))
+impl From<&Grid<Cucumber>> for Population {
+    fn from(grid: &Grid<Cucumber>) -> Self {
+        let (rows, cols) = (grid.rows(), grid.cols());
+        let mut pop = Population {
+            rows,
+            cols,
+            easts: vec![],
+            souths: vec![],
+        };
+        pop.easts = vec![pop.empty_row(); rows];
+        pop.souths = vec![pop.empty_row(); rows];
+        for (r, c) in grid.coords() {
+            match grid.get(r, c) {
+                Cucumber::East => Self::set(&mut pop.easts[r], c),
+                Cucumber::South => Self::set(&mut pop.souths[r], c),
+                Cucumber::Empty => {}
+            }
+        }
+        pop
     }
 }
 
-fn solve_part1(cucumbers: &Population) -> usize {
-    let mut steps = 0;
-
-    let mut pop = cucumbers.clone();
+/// Cucumbers that moved in single step, as row bitsets of their positions before the move.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Moves {
+    cols: usize,
+    easts: Vec<Row>,
+    souths: Vec<Row>,
+}
 
-    let mut done_something = true;
-    while done_something {
-        done_something = false;
+impl Moves {
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
 
-        let moving: Vec<(usize, usize)> = pop
-            .easts
-            .iter()
-            .filter(|(r, c)| pop.free_east(*r, *c))
-            .copied()
-            .collect();
+    pub fn count(&self) -> usize {
+        let ones = |rows: &Vec<Row>| -> usize {
+            rows.iter().flatten().map(|w| w.count_ones() as usize).sum()
+        };
+        ones(&self.easts) + ones(&self.souths)
+    }
 
-        for (r, c) in moving {
-            pop.easts.remove(&(r, c));
-            pop.easts.insert((r, (c + 1) % pop.cols));
-            done_something = true;
+    fn positions(&self, rows: &[Row]) -> Vec<(usize, usize)> {
+        let mut positions = vec![];
+        for (r, row) in rows.iter().enumerate() {
+            for c in 0..self.cols {
+                if Population::get(row, c) {
+                    positions.push((r, c));
+                }
+            }
         }
+        positions
+    }
 
-        let moving: Vec<(usize, usize)> = pop
-            .souths
-            .iter()
-            .filter(|(r, c)| pop.free_south(*r, *c))
-            .copied()
-            .collect();
-
-        for (r, c) in moving {
-            pop.souths.remove(&(r, c));
-            pop.souths.insert(((r + 1) % pop.rows, c));
-            done_something = true;
-        }
+    #[allow(dead_code)]
+    pub fn east_positions(&self) -> Vec<(usize, usize)> {
+        self.positions(&self.easts)
+    }
 
-        steps += 1;
+    #[allow(dead_code)]
+    pub fn south_positions(&self) -> Vec<(usize, usize)> {
+        self.positions(&self.souths)
     }
+}
 
-    steps
+fn solve_part1(cucumbers: &Population) -> usize {
+    cucumbers.clone().run(None).unwrap()
 }
 
 fn solve_part2(_cucumbers: &Population) -> usize {
@@ -117,6 +336,122 @@ fn solve_part2(_cucumbers: &Population) -> usize {
     0
 }
 
-generate_main!();
+/// Run with `-- --frames <file> [every]` to also dump every n-th state of the sea floor,
+/// or with `-- --after <steps>` to print the state after given number of steps.
+fn main() {
+    let data_str = utils::string_from_input(DAY_ID);
+    let data;
+    print_timed_result!("parse input", data = parse_input(&data_str));
+    print_timed_result!("part 1", solve_part1(&data));
+    print_timed_result!("part 2", solve_part2(&data));
+
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(pos) = args.iter().position(|arg| arg == "--frames") {
+        let path = args.get(pos + 1).expect("missing frames file path");
+        let every = args.get(pos + 2).map_or(1, |n| n.parse().unwrap());
+        let mut out = BufWriter::new(File::create(path).unwrap());
+        data.clone().run_with_frames(None, Some((&mut out, every)));
+        println!("frames written to {}", path);
+    }
+    if let Some(pos) = args.iter().position(|arg| arg == "--after") {
+        let steps = args
+            .get(pos + 1)
+            .expect("missing step count")
+            .parse()
+            .unwrap();
+        println!("After {} steps:\n{}", steps, data.after(steps).render());
+    }
+}
 
 generate_tests!(58, 0);
+
+#[cfg(test)]
+mod population_tests {
+    use super::*;
+    use automaton::Automaton;
+
+    #[test]
+    fn bitsets_match_automaton() {
+        // wide enough to span multiple words per row
+        let mut data = String::new();
+        for r in 0..7 {
+            let line: String = (0..150)
+                .map(|c| match (r * 7 + c * 13) % 5 {
+                    0 | 1 => '>',
+                    2 => 'v',
+                    _ => '.',
+                })
+                .collect();
+            data += &line;
+            data += "\n";
+        }
+        for data in [data, utils::string_from_sample(DAY_ID)] {
+            let grid = parse_grid(&data);
+            let mut pop = Population::from(&grid);
+            let mut automaton = Automaton::new(HerdRule, grid);
+            for _ in 0..20 {
+                let moved = !pop.step().is_empty();
+                assert_eq!(moved, automaton.step_changed());
+                assert_eq!(pop, Population::from(&automaton.grid));
+            }
+        }
+    }
+
+    #[test]
+    fn worked_example() {
+        let pop = parse_input(&utils::string_from_sample(DAY_ID));
+        assert_eq!(
+            pop.after(1).render(),
+            "....>.>v.>\n\
+             v.v>.>v.v.\n\
+             >v>>..>v..\n\
+             >>v>v>.>.v\n\
+             .>v.v...v.\n\
+             v>>.>vvv..\n\
+             ..v...>>..\n\
+             vv...>>vv.\n\
+             >.v.v..v.v\n"
+        );
+        assert_eq!(pop.after(58), pop.after(57));
+        assert_ne!(pop.after(57), pop.after(56));
+    }
+
+    #[test]
+    fn step_moves() {
+        let mut pop = parse_input("...>>>>>...");
+        let moves = pop.step();
+        assert_eq!(moves.east_positions(), vec![(0, 7)]);
+        assert!(moves.south_positions().is_empty());
+        assert_eq!(pop.render(), "...>>>>.>..\n");
+
+        let mut pop = parse_input(".>\n.v");
+        assert_eq!(pop.step().count(), 2);
+        assert_eq!(pop.render(), ">v\n..\n");
+    }
+
+    #[test]
+    fn run_limits() {
+        let pop = parse_input(&utils::string_from_sample(DAY_ID));
+        assert_eq!(pop.clone().run(Some(57)), None);
+        assert_eq!(pop.clone().run(Some(58)), Some(58));
+
+        let mut out: Vec<u8> = vec![];
+        let result = pop.clone().run_with_frames(None, Some((&mut out, 10)));
+        assert_eq!(result, Some(58));
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("After").count(), 6);
+        assert!(out.contains("After 10 steps:\n..>..>>vv.\nv.....>>.v\n"));
+    }
+
+    #[test]
+    fn undo_steps() {
+        let pop = parse_input(&utils::string_from_sample(DAY_ID));
+        let mut current = pop.clone();
+        let history: Vec<Moves> = (0..10).map(|_| current.step()).collect();
+        assert_eq!(current, pop.after(10));
+        for moves in history.iter().rev() {
+            current.undo(moves);
+        }
+        assert_eq!(current, pop);
+    }
+}